@@ -0,0 +1,45 @@
+#![feature(test)]
+
+extern crate test;
+extern crate tl_sync;
+
+use test::Bencher;
+use tl_sync::*;
+
+#[bench]
+fn sync_1m_vec_10_changes(bencher: &mut Bencher) {
+    let ctx = SyncContext::new();
+    let _thread = ctx.register_thread(UI_THREAD);
+    {
+        let a: Tl<Vec<usize>> = Tl::new(vec![1; 1024 * 1024]);
+
+        bencher.iter(|| {
+            {
+                let mut a = a.write();
+                for i in 0..10 {
+                    a[i * 1000] += 1;
+                }
+            }
+            sync_from(ctx.mutate_index());
+        });
+    }
+}
+
+#[bench]
+fn sync_1m_tl_vec_10_changes(bencher: &mut Bencher) {
+    let ctx = SyncContext::new();
+    let _thread = ctx.register_thread(UI_THREAD);
+    {
+        let a: Tl<TlVec<usize>> = Tl::new(vec![1; 1024 * 1024].into());
+
+        bencher.iter(|| {
+            {
+                let mut a = a.write();
+                for i in 0..10 {
+                    *a.get_mut(i * 1000).unwrap() += 1;
+                }
+            }
+            sync_from(ctx.mutate_index());
+        });
+    }
+}
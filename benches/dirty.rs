@@ -0,0 +1,56 @@
+#![feature(test)]
+
+extern crate test;
+extern crate tl_sync;
+
+use test::{black_box, Bencher};
+use tl_sync::*;
+
+// Writes n Tls which are all already dirty, then syncs. With constant time
+// marking the 10k case takes about 10 times the 1k one, not 100 times
+fn write_already_dirty(bencher: &mut Bencher, n: usize) {
+    let ctx = SyncContext::new();
+    let _thread = ctx.register_thread(UI_THREAD);
+    {
+        let a: Vec<Tl<usize>> = (0..n).map(Tl::new).collect();
+
+        bencher.iter(|| {
+            for it in a.iter() {
+                *it.write_advanced() += 1;
+            }
+            sync_from(ctx.mutate_index());
+        });
+    }
+}
+
+#[bench]
+fn write_1k_already_dirty(bencher: &mut Bencher) {
+    write_already_dirty(bencher, 1_000);
+}
+
+#[bench]
+fn write_10k_already_dirty(bencher: &mut Bencher) {
+    write_already_dirty(bencher, 10_000);
+}
+
+// Reference for the above, marking dirty the way it was done before
+// the dirty list was indexed: box the Tl, then scan the list for it
+#[bench]
+fn linear_scan_10k_already_dirty(bencher: &mut Bencher) {
+    let ctx = SyncContext::new();
+    let _thread = ctx.register_thread(UI_THREAD);
+    {
+        let a: Vec<Tl<usize>> = (0..10_000).map(Tl::new).collect();
+        let mut dirties: Vec<Box<dyn Dirty>> = Vec::new();
+
+        bencher.iter(|| {
+            for it in a.iter() {
+                let d = it.boxed();
+                if !dirties.iter().any(|it| it.get_ptr() == d.get_ptr()) {
+                    dirties.push(d);
+                }
+            }
+            black_box(&dirties);
+        });
+    }
+}
@@ -10,7 +10,8 @@ use tl_sync::*;
 
 #[bench]
 fn sync_1mb_and_10k_100bytes(bencher: &mut Bencher) {
-    init_dirties();
+    let ctx = SyncContext::new();
+    let _thread = ctx.register_thread(UI_THREAD);
     {
         let a: Tl<Vec<u8>> = Tl::new(vec![1; 1024 * 1024]);
         let mut b: Vec<Tl<Vec<u8>>> = vec![];
@@ -23,5 +24,19 @@ fn sync_1mb_and_10k_100bytes(bencher: &mut Bencher) {
             b.par_iter().for_each(|it| it.sync(1, 0));
         });
     }
-    drop_dirties();
+}
+
+#[bench]
+fn sync_100_vecs_parallel(bencher: &mut Bencher) {
+    let ctx = SyncContext::new();
+    let _thread = ctx.register_thread(UI_THREAD);
+    ctx.set_parallel_sync(true);
+    {
+        let b: Vec<Tl<Vec<u8>>> = (1..100).map(|_| Tl::new(vec![1; 1000 * 100])).collect();
+
+        bencher.iter(|| {
+            b.iter().for_each(|it| it.write()[0] += 1);
+            sync_from(ctx.mutate_index());
+        });
+    }
 }
@@ -7,9 +7,6 @@ extern crate tl_sync;
 use iui::controls::Button;
 use iui::prelude::*;
 use rayon::prelude::*;
-use std::cell::Cell;
-use std::rc::Rc;
-use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 use tl_sync::*;
 
@@ -28,16 +25,8 @@ struct Counter {
     do_toast: Action<Toast>,
     time: Tl<Instant>,
     last_time: Tl<Instant>,
-    ticks: Tl<u64>,
     iui: Trust<UI>,
-    listeners: Arc<Mutex<Vec<ListenerHandleRef>>>,
-}
-
-impl Counter {
-    fn register_listener<T: GetPtr, U: 'static + FnMut() + Clone>(&self, v: &T, f: U) {
-        let mut l = self.listeners.lock().unwrap();
-        l.push(register_listener_1(v, f));
-    }
+    subs: Subscriptions,
 }
 
 impl UiSetup for Counter {
@@ -54,37 +43,25 @@ impl UiSetup for Counter {
             }
         });
 
-        self.register_listener(&self.counter, {
+        // At most once per tick, the counter may be notified in many rounds
+        spawn_local(self.counter.stream_with(|it| it[0]).throttle(1).for_each({
             let this = self.clone();
             let mut btn_test = btn_test.clone();
-            let last_ticks = Rc::new(Cell::new(0));
-
-            move || {
-                if *this.ticks <= last_ticks.get() {
-                    return;
-                }
-                last_ticks.set(*this.ticks);
 
+            move |counter| {
                 // let dt = *this.time - *this.last_time;
                 // println!("FPS: {}", 1000 / (dt.subsec_millis() + 1));
 
-                btn_test.set_text(&this.iui, &format!("Counter: {}", this.counter[0]));
+                btn_test.set_text(&this.iui, &format!("Counter: {}", counter));
             }
-        });
+        }));
 
-        self.register_listener(&self.do_toast, {
+        self.subs.add(self.do_toast.subscribe({
             let this = self.clone();
-            move || {
-                if this.do_toast.len() == 0 {
-                    return;
-                }
-
-                println!(
-                    "ui do_toast: {}, {}",
-                    this.do_toast[0].message, this.counter[0]
-                );
+            move |toasts| {
+                println!("ui do_toast: {}, {}", toasts[0].message, this.counter[0]);
             }
-        });
+        }));
 
         win.set_child(&self.iui, btn_test);
         win.show(&self.iui);
@@ -93,33 +70,29 @@ impl UiSetup for Counter {
 
 impl ComputeSetup for Counter {
     fn setup_compute(&self) {
-        self.register_listener(&self.counter, {
+        self.subs.listen(&[&self.counter], {
             let this = self.clone();
             move || {
                 if this.counter[0] < 250 {
-                    // for it in this.counter.to_mut().iter_mut() {
+                    // for it in this.counter.write().iter_mut() {
                     //     *it += 1;
                     // }
-                    this.counter.to_mut().par_iter_mut().for_each(|it| {
+                    this.counter.write().par_iter_mut().for_each(|it| {
                         *it += 1;
                     });
                 }
             }
         });
 
-        self.register_listener(&self.on_click, {
+        self.subs.add(self.on_click.subscribe({
             let this = self.clone();
-            move || {
-                if this.on_click.len() == 0 {
-                    return;
-                }
-
-                println!("compute on_click: {}", this.on_click[0].counter_at);
+            move |clicks| {
+                println!("compute on_click: {}", clicks[0].counter_at);
                 this.do_toast.fire(Toast {
-                    message: format!("Hello {}", this.counter[0]).into(),
+                    message: format!("Hello {}", this.counter[0]),
                 });
             }
-        });
+        }));
     }
 }
 
@@ -130,6 +103,10 @@ fn main() {
         .unwrap();
 
     let stop = {
+        let ctx = SyncContext::new();
+        let _thread = ctx.register_thread(UI_THREAD);
+        ctx.set_parallel_sync(true);
+        ctx.set_parallel_copy_threshold(64 * 1024);
         let iui = UI::init().unwrap();
         let root = Counter {
             counter: Tl::new(vec![0; 1024 * 1024 * 5]),
@@ -137,17 +114,15 @@ fn main() {
             do_toast: Action::new(),
             time: Tl::new(Instant::now()),
             last_time: Tl::new(Instant::now()),
-            ticks: Tl::new(0),
             iui: Trust::new(iui.clone()),
-            listeners: Default::default(),
+            subs: Subscriptions::new(),
         };
-        let (mut tick, stop) = setup(root.clone(), Duration::from_millis(15));
+        let (mut tick, stop) = setup(&ctx, root.clone(), Duration::from_millis(15));
         let mut ev = iui.event_loop();
 
         ev.on_tick(&iui, move || {
-            *root.last_time.to_mut() = *root.time;
-            *root.time.to_mut() = Instant::now();
-            *root.ticks.to_mut() += 1;
+            *root.last_time.write() = *root.time;
+            *root.time.write() = Instant::now();
             tick();
         });
 
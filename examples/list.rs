@@ -1,80 +1,61 @@
 extern crate tl_sync;
 
-use std::sync::Mutex;
 use std::time::Duration;
-use std::collections::HashMap;
 use tl_sync::*;
 
-#[derive(Clone)]
-struct VerifyAction<T: 'static + Clone> {
-    trigger: Action<T>,
-    verified: Action<T>,
-}
-
-impl<T: 'static + Clone> VerifyAction<T> {
-    fn new() -> Self {
-        Self {
-            trigger: Action::new(),
-            verified: Action::new(),
-        }
-    }
-
-    fn transfer(&self) {
-        for it in self.trigger.iter() {
-            self.verified.fire(it.clone());
-        }
-    }
-}
-
 #[derive(Clone)]
 struct Root {
     money: Tl<usize>,
-    item_map: Tl<HashMap<String, Item>>,
-    on_upgrade_item: VerifyAction<String>,
+    item_map: Tl<TlMap<String, Item>>,
+    on_upgrade_item: Request<String, String>,
     on_iap: Action<usize>,
-    listeners: Wrc<Mutex<Vec<ListenerHandleRef>>>,
+    subs: Subscriptions,
 }
 
 impl Root {
     fn new() -> Self {
         Self {
             money: Tl::new(100),
-            item_map: Tl::new(HashMap::new()),
-            on_upgrade_item: VerifyAction::new(),
+            item_map: Tl::new(TlMap::new()),
+            on_upgrade_item: Request::new(),
             on_iap: Action::new(),
-            listeners: Default::default(),
+            subs: Subscriptions::new(),
         }
     }
 
     fn clone_weak(&self) -> Self {
         let mut ret = self.clone();
 
-        ret.listeners.be_weak();
+        ret.subs.be_weak();
 
         ret
     }
 
     fn setup(&self) {
-        self.defer(register_listener_1(&self.on_upgrade_item.trigger, {
+        // Validation runs before the listeners applying accepted requests
+        self.subs.add(self.on_upgrade_item.requests().subscribe_in(Phase::Validate, {
             let this = self.clone_weak();
-            move || {
+            move |requests| {
                 let mut required_money = 0;
 
-                for item_id in this.on_upgrade_item.trigger.iter() {
+                for (_, item_id) in requests.iter() {
                     let item = this.item_map.get(item_id).unwrap();
 
                     required_money += *item.value;
                 }
 
-                if *this.money >= required_money {
-                    this.on_upgrade_item.transfer();
-                } else {
-                    println!("not enough money to upgrade item(s)");
+                let enough = *this.money >= required_money;
+                for &(ticket, ref item_id) in requests.iter() {
+                    if enough {
+                        this.on_upgrade_item.reply(ticket, Ok(item_id.clone()));
+                    } else {
+                        this.on_upgrade_item.reply(ticket, Err("not enough money".into()));
+                    }
                 }
             }
         }));
 
-        self.defer(register_listener_2(&self.on_upgrade_item.verified, &self.on_iap, {
+        self.subs.add(register_listener_2(self.on_upgrade_item.replies(), &self.on_iap, {
             let this = self.clone_weak();
             move || {
                 let mut inc = 0;
@@ -84,24 +65,23 @@ impl Root {
                     inc += it;
                 }
 
-                for it in this.on_upgrade_item.verified.iter() {
-                    let item = this.item_map.get(it).unwrap();
+                for (_, result) in this.on_upgrade_item.replies().iter() {
+                    if let Ok(ref item_id) = *result {
+                        let item = this.item_map.get(item_id).unwrap();
 
-                    dec += *item.value;
+                        dec += *item.value;
+                    }
                 }
 
-                assert!(*this.money + inc <= 2_000_000_000, "overflow");
-                assert!(dec <= *this.money + inc, "invalid money inc/dec");
-                *this.money.to_mut() = *this.money + inc - dec;
-                println!("money: {}", *this.money + inc - dec);
+                let mut money = this.money.write();
+                *money += inc;
+                assert!(*money <= 2_000_000_000, "overflow");
+                assert!(dec <= *money, "invalid money inc/dec");
+                *money -= dec;
+                println!("money: {}", *money);
             }
         }));
     }
-
-    fn defer(&self, h: ListenerHandleRef) {
-        let mut l = self.listeners.lock().unwrap();
-        l.push(h);
-    }
 }
 
 #[derive(Clone)]
@@ -109,8 +89,8 @@ struct Item {
     id: String,
     value: Tl<usize>,
     // Demo only, action should be at top level
-    on_use: VerifyAction<usize>,
-    listeners: Wrc<Mutex<Vec<ListenerHandleRef>>>,
+    on_use: Request<usize, usize>,
+    subs: Subscriptions,
 }
 
 impl Item {
@@ -118,71 +98,78 @@ impl Item {
         Self {
             id,
             value: Tl::new(value),
-            on_use: VerifyAction::new(),
-            listeners: Default::default(),
+            on_use: Request::new(),
+            subs: Subscriptions::new(),
         }
     }
 
     fn clone_weak(&self) -> Self {
         let mut ret = self.clone();
 
-        ret.listeners.be_weak();
+        ret.subs.be_weak();
 
         ret
     }
 
-    fn setup(&self, on_upgrade_item: &VerifyAction<String>) {
-        self.defer(register_listener_1(&self.on_use.trigger, {
+    fn setup(&self, on_upgrade_item: &Request<String, String>) {
+        self.subs.add(self.on_use.requests().subscribe_in(Phase::Validate, {
             let this = self.clone_weak();
-            move || {
+            move |requests| {
                 let mut required_value = 0;
 
-                for it in this.on_use.trigger.iter() {
-                    required_value += it;
+                for &(_, value) in requests.iter() {
+                    required_value += value;
                 }
 
-                if *this.value > required_value {
-                    this.on_use.transfer();
-                } else {
-                    // TODO Show/Toast error to UI
+                let enough = *this.value > required_value;
+                for &(ticket, value) in requests.iter() {
+                    if enough {
+                        this.on_use.reply(ticket, Ok(value));
+                    } else {
+                        this.on_use.reply(ticket, Err(format!("{} has not enough value", this.id)));
+                    }
                 }
             }
         }));
 
-        self.defer(register_listener_2(&on_upgrade_item.verified, &self.on_use.verified, {
+        self.subs.add(register_listener_2(on_upgrade_item.replies(), self.on_use.replies(), {
             let this = self.clone_weak();
             let on_upgrade_item = on_upgrade_item.clone();
             move || {
                 let mut inc = 0;
                 let mut dec = 0;
 
-                for it in on_upgrade_item.verified.iter() {
-                    if *it == this.id {
-                        inc += 10;
+                for (_, result) in on_upgrade_item.replies().iter() {
+                    if let Ok(ref item_id) = *result {
+                        if *item_id == this.id {
+                            inc += 10;
+                        }
                     }
                 }
 
-                for it in this.on_use.verified.iter() {
-                    dec += it;
+                for (_, result) in this.on_use.replies().iter() {
+                    if let Ok(value) = *result {
+                        dec += value;
+                    }
                 }
 
                 if inc == 0 && dec == 0 { return; }
-                assert!(dec <= *this.value + inc);
-                *this.value.to_mut() = *this.value + inc - dec;
-                println!("{} value: {}", this.id, *this.value + inc - dec);
+                let mut value = this.value.write();
+                *value += inc;
+                assert!(dec <= *value);
+                *value -= dec;
+                println!("{} value: {}", this.id, *value);
             }
         }));
     }
-
-    fn defer(&self, h: ListenerHandleRef) {
-        let mut l = self.listeners.lock().unwrap();
-        l.push(h);
-    }
 }
 
 impl UiSetup for Root {
     fn setup_ui(&self) {
-        //
+        self.subs.add(self.on_upgrade_item.on_reply(|ticket, result| match *result {
+            Ok(ref item_id) => println!("ui: {:?} upgraded {}", ticket, item_id),
+            Err(ref e) => println!("ui: {:?} rejected, {}", ticket, e),
+        }));
     }
 }
 
@@ -190,7 +177,7 @@ impl ComputeSetup for Root {
     fn setup_compute(&self) {
         self.setup();
 
-        let item_map = self.item_map.to_mut();
+        let mut item_map = self.item_map.write();
         item_map.insert(
             "i001".into(),
             Item::new("i001".into(), 19)
@@ -202,17 +189,19 @@ impl ComputeSetup for Root {
         item_map.get("i001").unwrap().setup(&self.on_upgrade_item);
         item_map.get("i002").unwrap().setup(&self.on_upgrade_item);
 
-        self.on_upgrade_item.trigger.fire("i001".into());
-        self.on_upgrade_item.trigger.fire("i001".into());
-        self.on_upgrade_item.trigger.fire("i002".into());
-        item_map.get("i001").unwrap().on_use.trigger.fire(5);
+        self.on_upgrade_item.send("i001".into());
+        self.on_upgrade_item.send("i001".into());
+        self.on_upgrade_item.send("i002".into());
+        item_map.get("i001").unwrap().on_use.send(5);
     }
 }
 
 fn main() {
     let stop = {
+        let ctx = SyncContext::new();
+        let _thread = ctx.register_thread(UI_THREAD);
         let root = Root::new();
-        let (mut tick, stop) = setup(root.clone(), Duration::from_millis(1));
+        let (mut tick, stop) = setup(&ctx, root.clone(), Duration::from_millis(1));
         
         for _ in 1..10 {
             tick();
@@ -4,7 +4,8 @@ use std::thread;
 use tl_sync::*;
 
 fn main() {
-    init_dirties();
+    let ctx = SyncContext::new();
+    let _thread = ctx.register_thread(UI_THREAD);
     {
         let thing: Tl<String> = Tl::new("banana".into());
 
@@ -18,11 +19,13 @@ fn main() {
 
         let thread = {
             let thing = thing.clone();
+            let ctx = ctx.clone();
 
             thread::Builder::new()
                 .name("1_test".into())
                 .spawn(move || {
-                    *thing.to_mut() = "orange".into();
+                    let _thread = ctx.register_thread(COMPUTE_THREAD);
+                    *thing.write() = "orange".into();
                     sync_from(2);
                     sync_to(0);
                 }).unwrap()
@@ -31,5 +34,4 @@ fn main() {
         thread.join().unwrap();
         peek_notify(prepare_peek_notify());
     }
-    drop_dirties();
 }
@@ -1,34 +1,34 @@
 extern crate tl_sync;
 
 use std::thread;
-use std::sync::Mutex;
 use tl_sync::*;
 
 #[derive(Clone)]
 struct Container {
     thing: Tl<String>,
-    listeners: Wrc<Mutex<Vec<ListenerHandleRef>>>,
+    subs: Subscriptions,
 }
 
 impl Container {
     fn clone_weak(&self) -> Self {
         let mut ret = self.clone();
         
-        ret.listeners.be_weak();
+        ret.subs.be_weak();
         
         ret
     }
 }
 
 fn main() {
-    init_dirties();
+    let ctx = SyncContext::new();
+    let _thread = ctx.register_thread(UI_THREAD);
     {
         let container = Container {
             thing: Tl::new("banana".into()),
-            listeners: Wrc::new(Mutex::new(vec![])),
+            subs: Subscriptions::new(),
         };
 
-        container.listeners.lock().unwrap().push(register_listener_1(&container.thing, {
+        container.subs.add(register_listener_1(&container.thing, {
             let container = container.clone_weak();
 
             move || {
@@ -38,13 +38,15 @@ fn main() {
 
         let thread = {
             let container = container.clone();
+            let ctx = ctx.clone();
 
             thread::Builder::new()
                 .name("1_test".into())
                 .spawn(move || {
-                    *container.thing.to_mut() = "orange".into();
+                    let _thread = ctx.register_thread(COMPUTE_THREAD);
+                    *container.thing.write() = "orange".into();
 
-                    container.listeners.lock().unwrap().push(register_listener_1(&container.thing, {
+                    container.subs.add(register_listener_1(&container.thing, {
                         let container = container.clone_weak();
 
                         move || {
@@ -55,6 +57,7 @@ fn main() {
                     sync_from(2);
                     peek_notify(prepare_peek_notify());
                     sync_to(0);
+                    sync_clear();
                 }).unwrap()
         };
 
@@ -62,6 +65,5 @@ fn main() {
         peek_notify(prepare_peek_notify());
         sync_clear();
     }
-    ensure_empty_dirties();
-    drop_dirties();
+    ctx.ensure_empty();
 }
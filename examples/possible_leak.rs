@@ -4,7 +4,8 @@ use std::thread;
 use tl_sync::*;
 
 fn main() {
-    init_dirties();
+    let ctx = SyncContext::new();
+    let _thread = ctx.register_thread(UI_THREAD);
     {
         let tmp = Tl::new(1);
         let a = Tl::new((true, tmp));
@@ -13,10 +14,12 @@ fn main() {
 
         {
             let a = a.clone();
+            let ctx = ctx.clone();
             thread::Builder::new()
                 .name("1_test".into())
                 .spawn(move || {
-                    a.to_mut().1 = Tl::new(3);
+                    let _thread = ctx.register_thread(COMPUTE_THREAD);
+                    a.write().1 = Tl::new(3);
                     // Possible leak if not using Arc inside Tl
                     let _not_leak = Tl::new(100);
                     sync_from(2);
@@ -28,5 +31,4 @@ fn main() {
 
         println!("{}", *a.1);
     }
-    drop_dirties();
 }
@@ -5,7 +5,7 @@ use std::thread;
 use std::time;
 use tl_sync::*;
 
-#[derive(Clone)]
+#[derive(Clone, ManualCopy)]
 struct SceneRoot {
     stack: Tl<Vec<Scene>>,
     popup: Tl<Option<Scene>>,
@@ -22,31 +22,27 @@ impl Default for SceneRoot {
     }
 }
 
-impl ManualCopy<SceneRoot> for SceneRoot {
-    fn copy_from(&mut self, _other: &mut SceneRoot) {
-        panic!("SHOULD NEVER BE CALLED");
-    }
-}
-
-#[derive(Default, Clone)]
+#[derive(Default, Clone, ManualCopy)]
 struct Scene {
     title: Tl<String>,
     buttons: Tl<Vec<Button>>,
+    #[manual_copy(clone)]
     image_data: Arc<Vec<u8>>,
 }
 
-#[derive(Default, Clone)]
+#[derive(Default, Clone, ManualCopy)]
 struct Button {
     pos: Tl<(u32, u32)>,
     txt: Tl<String>,
 }
 
 fn main() {
-    init_dirties();
+    let ctx = SyncContext::new();
+    let _thread = ctx.register_thread(UI_THREAD);
     {
         let r = Arc::new(SceneRoot::default());
 
-        r.stack.to_mut().push(Scene {
+        r.stack.write().push(Scene {
             title: Tl::new("Home".into()),
             buttons: Tl::new(vec![Button {
                 pos: Tl::new((100, 50)),
@@ -64,14 +60,16 @@ fn main() {
 
         let handle = {
             let r = r.clone();
+            let ctx = ctx.clone();
             thread::Builder::new()
                 .name("1_test".into())
                 .spawn(move || {
+                    let _thread = ctx.register_thread(COMPUTE_THREAD);
                     for _ in 1..10 {
                         {
                             let tmp = &r.stack[0].buttons[0];
-                            *tmp.txt.to_mut() = "Play".into();
-                            *tmp.pos.to_mut() = (tmp.pos.0 - 2, tmp.pos.1 + 3);
+                            *tmp.txt.write() = "Play".into();
+                            *tmp.pos.write() = (tmp.pos.0 - 2, tmp.pos.1 + 3);
                         }
 
                         sync_from(2);
@@ -89,5 +87,4 @@ fn main() {
             *r.stack[0].title, *r.stack[0].buttons[0].txt, *r.stack[0].buttons[0].pos
         );
     }
-    drop_dirties();
 }
@@ -4,17 +4,20 @@ use std::thread;
 use tl_sync::*;
 
 fn main() {
-    init_dirties();
+    let ctx = SyncContext::new();
+    let _thread = ctx.register_thread(UI_THREAD);
     {
         let thing: Tl<String> = Tl::new("banana".into());
 
         let thread = {
             let thing = thing.clone();
+            let ctx = ctx.clone();
 
             thread::Builder::new()
                 .name("1_test".into())
                 .spawn(move || {
-                    *thing.to_mut() = "orange".into();
+                    let _thread = ctx.register_thread(COMPUTE_THREAD);
+                    *thing.write() = "orange".into();
                     thread::park();
                     sync_from(2);
                     sync_to(0);
@@ -26,5 +29,4 @@ fn main() {
         thread.join().unwrap();
         println!("different now, thing = {}", *thing);
     }
-    drop_dirties();
 }
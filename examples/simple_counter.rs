@@ -5,7 +5,6 @@ extern crate tl_sync;
 
 use iui::controls::{Button, HorizontalBox, Label};
 use iui::prelude::*;
-use std::sync::Mutex;
 use std::time::Duration;
 use tl_sync::*;
 
@@ -15,7 +14,7 @@ struct Counter {
     on_inc: Action<()>,
     on_dec: Action<()>,
     iui: Trust<UI>,
-    listeners: Wrc<Mutex<Vec<ListenerHandleRef>>>,
+    subs: Subscriptions,
 }
 
 impl Counter {
@@ -25,33 +24,28 @@ impl Counter {
             on_inc: Action::new(),
             on_dec: Action::new(),
             iui: Trust::new(iui),
-            listeners: Default::default(),
+            subs: Subscriptions::new(),
         }
     }
 
     fn clone_weak(&self) -> Self {
         let mut ret = self.clone();
 
-        ret.listeners.be_weak();
+        ret.subs.be_weak();
 
         ret
     }
 
     fn setup(&self) {
-        self.defer(register_listener_2(&self.on_inc, &self.on_dec, {
+        self.subs.add(register_listener_2(&self.on_inc, &self.on_dec, {
             let this = self.clone_weak();
             move || {
-                let value = this.value.to_mut();
+                let mut value = this.value.write();
                 *value += this.on_inc.len() as isize;
                 *value -= this.on_dec.len() as isize;
             }
         }));
     }
-
-    fn defer(&self, h: ListenerHandleRef) {
-        let mut l = self.listeners.lock().unwrap();
-        l.push(h);
-    }
 }
 
 impl UiSetup for Counter {
@@ -85,7 +79,7 @@ impl UiSetup for Counter {
         win.set_child(&self.iui, hbox.clone());
         win.show(&self.iui);
 
-        self.defer(register_listener_1(&self.value, {
+        self.subs.add(register_listener_1(&self.value, {
             let this = self.clone_weak();
             let mut lbl_value = lbl_value.clone();
             move || {
@@ -103,9 +97,11 @@ impl ComputeSetup for Counter {
 
 fn main() {
     let stop = {
+        let ctx = SyncContext::new();
+        let _thread = ctx.register_thread(UI_THREAD);
         let iui = UI::init().unwrap();
         let root = Counter::new(iui.clone());
-        let (mut tick, stop) = setup(root.clone(), Duration::from_millis(15));
+        let (mut tick, stop) = setup(&ctx, root.clone(), Duration::from_millis(15));
         let mut ev = iui.event_loop();
 
         ev.on_tick(&iui, move || {
@@ -4,13 +4,15 @@ use std::cell::RefCell;
 use std::rc::Rc;
 use tl_sync::*;
 
+type Listeners = Rc<RefCell<Vec<Box<dyn Fn()>>>>;
+
 #[derive(Default, Clone)]
 struct Emitter {
-    l: Rc<RefCell<Vec<Box<Fn()>>>>,
+    l: Listeners,
 }
 
 impl Emitter {
-    fn add_listener(&self, f: Box<Fn()>) {
+    fn add_listener(&self, f: Box<dyn Fn()>) {
         let mut l = self.l.borrow_mut();
 
         l.push(f);
@@ -59,7 +61,7 @@ impl Screen {
         let mut elements = self.elements.borrow_mut();
 
         elements.push(Default::default());
-        let ref e = elements[0];
+        let e = &elements[0];
 
         {
             let this = self.clone_weak();
@@ -1,40 +1,323 @@
 use super::*;
-use std::ops::Deref;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::mem;
+use std::ops::{Deref, Index};
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
 
-struct Wrapper<T>(Vec<T>);
+// What fire does when an action already holds its capacity of events
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Overflow {
+    DropOldest,
+    DropNewest,
+    Panic,
+}
+
+// Events of an action seen by one thread, batches are shared
+// between every thread the events are fanned out to
+pub struct Events<T> {
+    batches: Vec<Arc<Vec<T>>>,
+}
+
+impl<T> Clone for Events<T> {
+    fn clone(&self) -> Self {
+        Self {
+            batches: self.batches.clone(),
+        }
+    }
+}
+
+impl<T> Default for Events<T> {
+    fn default() -> Self {
+        Self { batches: vec![] }
+    }
+}
+
+impl<T> Events<T> {
+    pub fn len(&self) -> usize {
+        self.batches.iter().map(|it| it.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.batches.iter().all(|it| it.is_empty())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.batches.iter().flat_map(|it| it.iter())
+    }
+
+    pub fn get(&self, i: usize) -> Option<&T> {
+        self.iter().nth(i)
+    }
+}
+
+impl<T: Clone> Events<T> {
+    pub fn to_vec(&self) -> Vec<T> {
+        self.iter().cloned().collect()
+    }
+}
+
+impl<T> Index<usize> for Events<T> {
+    type Output = T;
+
+    fn index(&self, i: usize) -> &T {
+        match self.get(i) {
+            Some(it) => it,
+            None => panic!("Event index {} out of range, there are {}", i, self.len()),
+        }
+    }
+}
+
+struct Wrapper<T> {
+    // Fired since the last sync_from, only used in the mutation slot
+    pending: VecDeque<T>,
+    // Visible on this thread until sync_clear
+    events: Events<T>,
+    // Received by sync_from, not yet passed on by sync_to
+    outgoing: Vec<Arc<Vec<T>>>,
+    // Batches of events taken by drain, they stay for deref until sync_clear
+    drained: AtomicUsize,
+}
+
+impl<T> Default for Wrapper<T> {
+    fn default() -> Self {
+        Self {
+            pending: VecDeque::new(),
+            events: Default::default(),
+            outgoing: vec![],
+            drained: AtomicUsize::new(0),
+        }
+    }
+}
 
 pub struct Action<T> {
     queue: Tl<Wrapper<T>>,
+    capacity: Option<(usize, Overflow)>,
 }
 
 impl<T> Clone for Action<T> {
     fn clone(&self) -> Self {
         Self {
             queue: self.queue.clone(),
+            capacity: self.capacity,
         }
     }
 }
 
 impl<T> Deref for Action<T> {
-    type Target = Vec<T>;
+    type Target = Events<T>;
 
-    fn deref(&self) -> &Vec<T> {
-        &self.queue.0
+    fn deref(&self) -> &Events<T> {
+        &self.queue.events
     }
 }
 
-impl<T: 'static> Action<T> {
+impl<T: 'static + Send + Sync> Default for Action<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: 'static + Send + Sync> Action<T> {
     pub fn new() -> Self {
-        // TODO Find a way that flexible with thread
-        let a = [Wrapper(vec![]), Wrapper(vec![]), Wrapper(vec![])];
+        let threads = SyncContext::current().threads();
+        let a = (0..threads).map(|_| Wrapper::default()).collect();
 
         Self {
             queue: Tl::new_advanced(a),
+            capacity: None,
         }
     }
 
+    // At most capacity events are kept between two syncs of the firing thread
+    pub fn with_capacity(capacity: usize, overflow: Overflow) -> Self {
+        assert!(capacity > 0, "Action capacity must be at least 1");
+        let mut ret = Self::new();
+        ret.capacity = Some((capacity, overflow));
+
+        ret
+    }
+
     pub fn fire(&self, a: T) {
-        self.queue.to_mut_advanced().0.push(a);
+        let dropped = {
+            let mut q = self.queue.write_inner(false);
+            let pending = &mut q.pending;
+            let mut dropped = None;
+
+            if let Some((capacity, overflow)) = self.capacity {
+                if pending.len() >= capacity {
+                    match overflow {
+                        Overflow::DropOldest => dropped = pending.pop_front(),
+                        Overflow::DropNewest => return,
+                        Overflow::Panic => panic!(
+                            "Action {:#x} is full, capacity is {}",
+                            self.get_ptr(),
+                            capacity
+                        ),
+                    }
+                }
+            }
+
+            pending.push_back(a);
+            dropped
+        };
+
+        if in_transaction() {
+            let queue = self.queue.clone();
+            let mut dropped = dropped;
+
+            // Undone latest first, which puts back what each fire dropped
+            on_rollback(Box::new(move || {
+                let mut q = queue.mutate_slot();
+                let pending = &mut q.pending;
+                pending.pop_back();
+                if let Some(it) = dropped.take() {
+                    pending.push_front(it);
+                }
+            }));
+        }
+    }
+}
+
+impl<T: 'static> Action<T> {
+    // Events not drained yet on this thread, left for other listeners
+    pub fn peek(&self) -> Events<T> {
+        let drained = self.queue.drained.load(Ordering::Relaxed);
+
+        Events {
+            batches: self.queue.events.batches[drained..].to_vec(),
+        }
+    }
+
+    // Takes the events for the whole thread, subscribers and futures
+    // notified after this see none. Deref still shows every event
+    pub fn drain(&self) -> Events<T> {
+        let ret = self.peek();
+        self.queue
+            .drained
+            .store(self.queue.events.batches.len(), Ordering::Relaxed);
+
+        ret
+    }
+
+    // Resolves with the events of the next notify on this thread which has
+    // any, events of later notifies are added until it is polled
+    pub fn next(&self) -> Next<T> {
+        let (signal, handle) = self.listen_events();
+
+        Next {
+            signal,
+            _handle: handle,
+        }
+    }
+
+    fn listen_events(&self) -> (Rc<RefCell<Signal<Events<T>>>>, ListenerHandleRef) {
+        let signal: Rc<RefCell<Signal<Events<T>>>> = Signal::new();
+        let handle = listen_ptrs(
+            Phase::default(),
+            &[self.get_ptr()],
+            Rc::new(RefCell::new({
+                let this = self.clone();
+                let signal = signal.clone();
+                move || {
+                    let events = this.peek();
+                    if events.is_empty() {
+                        return;
+                    }
+
+                    let mut signal = signal.borrow_mut();
+                    match signal.value {
+                        Some(ref mut value) => value.batches.extend(events.batches.iter().cloned()),
+                        None => signal.value = Some(events.clone()),
+                    }
+                    signal.wake();
+                }
+            })),
+        );
+
+        (signal, handle)
+    }
+
+    // Called only when this action has events not drained yet, not on
+    // registration. Each subscriber gets its own copy of the events
+    pub fn subscribe<F: 'static + FnMut(&Events<T>)>(&self, f: F) -> ListenerHandleRef {
+        self.subscribe_in(Phase::default(), f)
+    }
+
+    pub fn subscribe_in<F>(&self, phase: Phase, f: F) -> ListenerHandleRef
+    where
+        F: 'static + FnMut(&Events<T>),
+    {
+        let this = self.clone();
+        let mut f = f;
+
+        listen_ptrs(
+            phase,
+            &[self.get_ptr()],
+            Rc::new(RefCell::new(move || {
+                let events = this.peek();
+                if !events.is_empty() {
+                    f(&events);
+                }
+            })),
+        )
+    }
+}
+
+pub struct Next<T> {
+    signal: Rc<RefCell<Signal<Events<T>>>>,
+    _handle: ListenerHandleRef,
+}
+
+impl<T> Future for Next<T> {
+    type Output = Events<T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Events<T>> {
+        self.signal.borrow_mut().poll(cx)
+    }
+}
+
+impl<T: 'static + Clone> Action<T> {
+    // Every event fired, in order, as notified on this thread
+    pub fn stream(&self) -> Fired<T> {
+        let (signal, handle) = self.listen_events();
+
+        Fired {
+            signal,
+            buffered: VecDeque::new(),
+            _handle: handle,
+        }
+    }
+}
+
+pub struct Fired<T> {
+    signal: Rc<RefCell<Signal<Events<T>>>>,
+    buffered: VecDeque<T>,
+    _handle: ListenerHandleRef,
+}
+
+// Events are never pinned
+impl<T> Unpin for Fired<T> {}
+
+impl<T: Clone> Stream for Fired<T> {
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<T>> {
+        let this = self.get_mut();
+
+        if this.buffered.is_empty() {
+            match this.signal.borrow_mut().poll(cx) {
+                Poll::Ready(events) => this.buffered.extend(events.iter().cloned()),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+
+        Poll::Ready(this.buffered.pop_front())
     }
 }
 
@@ -44,13 +327,35 @@ impl<T> GetPtr for Action<T> {
     }
 }
 
+impl<T> ManualCopy<Action<T>> for Action<T> {
+    fn copy_from(&mut self, other: &mut Action<T>) {
+        if self.get_ptr() != other.get_ptr() {
+            *self = other.clone();
+        }
+    }
+}
+
 impl<T> ManualCopy<Wrapper<T>> for Wrapper<T> {
     fn copy_from(&mut self, other: &mut Wrapper<T>) {
-        self.0.clear();
-        self.0.append(&mut other.0);
+        if !other.pending.is_empty() {
+            // From the mutation slot, the batch is kept to pass on later
+            let batch = Arc::new(Vec::from(mem::take(&mut other.pending)));
+            self.events.batches.push(batch.clone());
+            self.outgoing.push(batch);
+        } else {
+            // From another reader, share its batches without moving events
+            self.events
+                .batches
+                .extend(other.outgoing.iter().cloned());
+        }
     }
 
     fn clear(&mut self) {
-        self.0.clear();
+        self.events.batches.clear();
+        *self.drained.get_mut() = 0;
+    }
+
+    fn forwarded(&mut self) {
+        self.outgoing.clear();
     }
 }
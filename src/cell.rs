@@ -1,38 +1,142 @@
 use super::*;
 use std::cell::UnsafeCell;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
 pub struct TrustCell<T> {
-    pub arr: UnsafeCell<[T; THREADS]>,
+    pub arr: UnsafeCell<Vec<T>>,
+    writing: AtomicBool,
+    // TlRefs alive into the mutation slot, which cannot be written meanwhile
+    readers: AtomicUsize,
+    read_own_writes: AtomicBool,
+    // Context whose slot layout arr follows, None for the context's own cells
+    owner: Option<WeakSyncContext>,
 }
 
-unsafe impl<T> Sync for TrustCell<T> {}
+// Each slot is used by a different thread, and values are copied
+// between slots by whichever thread syncs them
+unsafe impl<T: Send + Sync> Sync for TrustCell<T> {}
 
 impl<T> TrustCell<T> {
-    pub fn new(arr: [T; THREADS]) -> Self {
+    pub fn new(arr: Vec<T>) -> Self {
         Self {
             arr: UnsafeCell::new(arr),
+            writing: AtomicBool::new(false),
+            readers: AtomicUsize::new(0),
+            read_own_writes: AtomicBool::new(false),
+            owner: None,
         }
     }
 
+    pub fn owned(arr: Vec<T>, ctx: &SyncContext) -> Self {
+        assert!(arr.len() == ctx.threads(), "Tl needs exactly one value for each thread slot");
+        let mut ret = Self::new(arr);
+        ret.owner = Some(ctx.downgrade());
+
+        ret
+    }
+
+    pub fn check_owner(&self, ctx: &SyncContext) {
+        if let Some(ref owner) = self.owner {
+            assert!(owner.is(ctx), "Tl was created under another SyncContext");
+        }
+    }
+
+    pub fn is_current_owner(&self) -> bool {
+        match self.owner {
+            Some(ref owner) => owner.is_current(),
+            None => true,
+        }
+    }
+
+    pub fn check_current_owner(&self) {
+        assert!(self.is_current_owner(), "Tl was created under another SyncContext");
+    }
+
+    pub fn len(&self) -> usize {
+        unsafe { (&*self.arr.get()).len() }
+    }
+
     pub fn get(&self, i: usize) -> &T {
         unsafe { &(&*self.arr.get())[i] }
     }
 
-    pub fn to_mut(&self, i: usize) -> &mut T {
-        unsafe { &mut (&mut *self.arr.get())[i] }
+    // Caller must make sure nothing else references slot i
+    // while the returned reference is alive
+    #[allow(clippy::mut_from_ref, clippy::wrong_self_convention)]
+    pub unsafe fn to_mut(&self, i: usize) -> &mut T {
+        &mut (&mut *self.arr.get())[i]
+    }
+
+    // Writer and readers each flag themselves before checking the other,
+    // so one of them always sees the other
+    pub fn begin_write(&self) {
+        if self.writing.swap(true, Ordering::SeqCst) {
+            panic!("Tl is already being written, drop the previous TlMut first");
+        }
+        if self.readers.load(Ordering::SeqCst) > 0 {
+            self.writing.store(false, Ordering::SeqCst);
+            panic!("Tl pending value is being read, drop the TlRef first");
+        }
+    }
+
+    pub fn end_write(&self) {
+        self.writing.store(false, Ordering::SeqCst);
+    }
+
+    pub fn is_writing(&self) -> bool {
+        self.writing.load(Ordering::SeqCst)
+    }
+
+    pub fn begin_read(&self) {
+        self.readers.fetch_add(1, Ordering::SeqCst);
+        if self.writing.load(Ordering::SeqCst) {
+            self.readers.fetch_sub(1, Ordering::SeqCst);
+            panic!("Tl is being written, drop the TlMut before reading its pending value");
+        }
+    }
+
+    pub fn end_read(&self) {
+        self.readers.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    pub fn is_reading(&self) -> bool {
+        self.readers.load(Ordering::SeqCst) > 0
+    }
+
+    pub fn set_read_own_writes(&self, enabled: bool) {
+        self.read_own_writes.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn is_read_own_writes(&self) -> bool {
+        self.read_own_writes.load(Ordering::Relaxed)
     }
 }
 
 impl<T: ManualCopy<T>> TrustCell<T> {
     pub fn inner_manual_copy(&self, from: usize, to: usize) {
+        assert!(from != to, "Cannot sync slot {} to itself", from);
+        assert!(!self.is_writing(), "Cannot sync a Tl while it is being written");
+        assert!(
+            !self.is_reading() || (from != self.len() - 1 && to != self.len() - 1),
+            "Cannot sync a Tl while its pending value is read"
+        );
+
         unsafe {
-            (&mut *self.arr.get())[to].copy_from(&mut (&mut *self.arr.get())[from]);
+            let arr = &mut *self.arr.get();
+            let p = arr.as_mut_ptr();
+            (&mut *p.add(to)).copy_from(&mut *p.add(from));
         }
     }
 
     pub fn inner_manual_clear(&self, to: usize) {
         unsafe {
-            (&mut *self.arr.get())[to].clear();
+            self.to_mut(to).clear();
+        }
+    }
+
+    pub fn inner_manual_forwarded(&self, from: usize) {
+        unsafe {
+            self.to_mut(from).forwarded();
         }
     }
 }
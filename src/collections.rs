@@ -0,0 +1,283 @@
+use super::*;
+use std::cmp;
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+use std::ops::Deref;
+
+// Keep this many recent edits at least, older ones force a full copy
+const MIN_EDIT_LOG: usize = 64;
+
+// Every slot carries the version of the last edit it has seen, plus a log of
+// recent edits, so copy_from only replays what the other slot is missing.
+// This stays correct when one slot is copied into several others.
+#[derive(Clone)]
+struct EditLog<K> {
+    version: u64,
+    start: u64,
+    edits: VecDeque<(u64, K)>,
+}
+
+impl<K> Default for EditLog<K> {
+    fn default() -> Self {
+        Self {
+            version: 0,
+            start: 0,
+            edits: VecDeque::new(),
+        }
+    }
+}
+
+impl<K: Clone> EditLog<K> {
+    fn bump(&mut self) {
+        self.version += 1;
+    }
+
+    fn push(&mut self, key: K, max_len: usize) {
+        self.version += 1;
+        self.edits.push_back((self.version, key));
+
+        if self.edits.len() > cmp::max(MIN_EDIT_LOG, max_len) {
+            let (v, _) = self.edits.pop_front().unwrap();
+            self.start = v;
+        }
+    }
+
+    fn reset(&mut self) {
+        self.version += 1;
+        self.start = self.version;
+        self.edits.clear();
+    }
+
+    fn is_up_to_date(&self, other: &EditLog<K>) -> bool {
+        self.version == other.version
+    }
+
+    // Whether other still has every edit made after this version
+    fn can_replay(&self, other: &EditLog<K>) -> bool {
+        self.version >= other.start && self.version <= other.version
+    }
+
+    // Edits are ordered by version, so only walk the missing tail
+    fn missing<'a>(&self, other: &'a EditLog<K>) -> impl Iterator<Item = &'a K> {
+        let version = self.version;
+        other
+            .edits
+            .iter()
+            .rev()
+            .take_while(move |it| it.0 > version)
+            .map(|it| &it.1)
+    }
+
+    fn replay_from(&mut self, other: &EditLog<K>, max_len: usize) {
+        let n = self.missing(other).count();
+        let skip = other.edits.len() - n;
+        self.edits.extend(other.edits.iter().skip(skip).cloned());
+        self.version = other.version;
+
+        while self.edits.len() > cmp::max(MIN_EDIT_LOG, max_len) {
+            let (v, _) = self.edits.pop_front().unwrap();
+            self.start = v;
+        }
+    }
+
+    fn copy_from(&mut self, other: &EditLog<K>) {
+        self.version = other.version;
+        self.start = other.start;
+        self.edits.clone_from(&other.edits);
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct TlVec<T> {
+    items: Vec<T>,
+    log: EditLog<usize>,
+}
+
+impl<T> Deref for TlVec<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        &self.items
+    }
+}
+
+impl<T> From<Vec<T>> for TlVec<T> {
+    fn from(items: Vec<T>) -> Self {
+        Self {
+            items,
+            log: Default::default(),
+        }
+    }
+}
+
+impl<T> TlVec<T> {
+    pub fn new() -> Self {
+        Vec::new().into()
+    }
+
+    pub fn push(&mut self, value: T) {
+        let i = self.items.len();
+        self.items.push(value);
+        self.log.push(i, self.items.len() / 4);
+    }
+
+    pub fn pop(&mut self) -> Option<T> {
+        self.log.bump();
+        self.items.pop()
+    }
+
+    pub fn truncate(&mut self, len: usize) {
+        self.log.bump();
+        self.items.truncate(len);
+    }
+
+    pub fn clear(&mut self) {
+        self.log.bump();
+        self.items.clear();
+    }
+
+    pub fn set(&mut self, i: usize, value: T) {
+        self.items[i] = value;
+        self.log.push(i, self.items.len() / 4);
+    }
+
+    pub fn get_mut(&mut self, i: usize) -> Option<&mut T> {
+        if i >= self.items.len() {
+            return None;
+        }
+
+        self.log.push(i, self.items.len() / 4);
+        self.items.get_mut(i)
+    }
+
+    // Shifting changes, the next sync copies everything
+    pub fn insert(&mut self, i: usize, value: T) {
+        self.log.reset();
+        self.items.insert(i, value);
+    }
+
+    pub fn remove(&mut self, i: usize) -> T {
+        self.log.reset();
+        self.items.remove(i)
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        self.log.reset();
+        &mut self.items
+    }
+}
+
+impl<T: Send + Sync + Clone> ManualCopy<TlVec<T>> for TlVec<T> {
+    fn copy_from(&mut self, other: &mut TlVec<T>) {
+        if self.log.is_up_to_date(&other.log) {
+            return;
+        }
+
+        if self.log.can_replay(&other.log) {
+            let olen = other.items.len();
+            self.items.truncate(olen);
+
+            let slen = self.items.len();
+            for &i in self.log.missing(&other.log) {
+                if i < slen {
+                    self.items[i].clone_from(&other.items[i]);
+                }
+            }
+            self.items.extend_from_slice(&other.items[slen..]);
+            self.log.replay_from(&other.log, olen / 4);
+        } else {
+            self.items.copy_from(&mut other.items);
+            self.log.copy_from(&other.log);
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct TlMap<K: Eq + Hash, V> {
+    items: HashMap<K, V>,
+    log: EditLog<K>,
+}
+
+impl<K: Eq + Hash, V> Default for TlMap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Eq + Hash, V> Deref for TlMap<K, V> {
+    type Target = HashMap<K, V>;
+
+    fn deref(&self) -> &HashMap<K, V> {
+        &self.items
+    }
+}
+
+impl<K: Eq + Hash, V> From<HashMap<K, V>> for TlMap<K, V> {
+    fn from(items: HashMap<K, V>) -> Self {
+        Self {
+            items,
+            log: Default::default(),
+        }
+    }
+}
+
+impl<K: Eq + Hash, V> TlMap<K, V> {
+    pub fn new() -> Self {
+        HashMap::new().into()
+    }
+}
+
+impl<K: Clone + Eq + Hash, V> TlMap<K, V> {
+    pub fn insert(&mut self, k: K, v: V) -> Option<V> {
+        self.log.push(k.clone(), self.items.len() / 4);
+        self.items.insert(k, v)
+    }
+
+    pub fn remove(&mut self, k: &K) -> Option<V> {
+        let ret = self.items.remove(k);
+        if ret.is_some() {
+            self.log.push(k.clone(), self.items.len() / 4);
+        }
+
+        ret
+    }
+
+    pub fn get_mut(&mut self, k: &K) -> Option<&mut V> {
+        if !self.items.contains_key(k) {
+            return None;
+        }
+
+        self.log.push(k.clone(), self.items.len() / 4);
+        self.items.get_mut(k)
+    }
+
+    pub fn clear(&mut self) {
+        self.log.reset();
+        self.items.clear();
+    }
+}
+
+impl<K: Clone + Eq + Hash, V: Send + Sync + Clone> ManualCopy<TlMap<K, V>> for TlMap<K, V> {
+    fn copy_from(&mut self, other: &mut TlMap<K, V>) {
+        if self.log.is_up_to_date(&other.log) {
+            return;
+        }
+
+        if self.log.can_replay(&other.log) {
+            for k in self.log.missing(&other.log) {
+                match other.items.get(k) {
+                    Some(v) => {
+                        self.items.insert(k.clone(), v.clone());
+                    }
+                    None => {
+                        self.items.remove(k);
+                    }
+                }
+            }
+            self.log.replay_from(&other.log, other.items.len() / 4);
+        } else {
+            self.items.copy_from(&mut other.items);
+            self.log.copy_from(&other.log);
+        }
+    }
+}
@@ -0,0 +1,131 @@
+use super::*;
+use std::cell::RefCell;
+use std::ops::Deref;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex, Weak};
+
+thread_local!(static TRACKING: RefCell<Vec<Vec<usize>>> = const { RefCell::new(vec![]) });
+
+// Run f and return the pointers of every Tl read during it
+pub fn track<R, F: FnOnce() -> R>(f: F) -> (R, Vec<usize>) {
+    TRACKING.with(|t| t.borrow_mut().push(vec![]));
+    let ret = f();
+    let deps = TRACKING.with(|t| t.borrow_mut().pop().unwrap());
+
+    (ret, deps)
+}
+
+pub(crate) fn track_read(ptr: usize) {
+    TRACKING.with(|t| {
+        if let Some(deps) = t.borrow_mut().last_mut() {
+            if !deps.contains(&ptr) {
+                deps.push(ptr);
+            }
+        }
+    });
+}
+
+// Value derived from other Tls, re-evaluated on the thread that created it
+// whenever one of the Tls read in its last evaluation is notified
+pub struct Computed<T> {
+    value: Tl<T>,
+    handle: Arc<Mutex<Option<ListenerHandleRef>>>,
+}
+
+struct Eval<T> {
+    value: Tl<T>,
+    f: Box<dyn FnMut() -> T>,
+    deps: Vec<usize>,
+    handle: Weak<Mutex<Option<ListenerHandleRef>>>,
+}
+
+impl<T> Clone for Computed<T> {
+    fn clone(&self) -> Self {
+        Self {
+            value: self.value.clone(),
+            handle: self.handle.clone(),
+        }
+    }
+}
+
+impl<T> Deref for Computed<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T> GetPtr for Computed<T> {
+    fn get_ptr(&self) -> usize {
+        self.value.get_ptr()
+    }
+}
+
+// Computed is a handle, copying it shares the same value
+impl<T> ManualCopy<Computed<T>> for Computed<T> {
+    fn copy_from(&mut self, other: &mut Computed<T>) {
+        if !Arc::ptr_eq(&self.handle, &other.handle) {
+            *self = other.clone();
+        }
+    }
+}
+
+impl<T: 'static + Send + Sync + Clone + ManualCopy<T>> Computed<T> {
+    pub fn new<F: 'static + FnMut() -> T>(f: F) -> Self {
+        let mut f = f;
+        let (value, deps) = track(&mut f);
+        let value = Tl::new(value);
+        let handle = Arc::new(Mutex::new(None));
+
+        let eval = Rc::new(RefCell::new(Eval {
+            value: value.clone(),
+            f: Box::new(f),
+            deps: vec![],
+            handle: Arc::downgrade(&handle),
+        }));
+        Self::subscribe(&eval, deps);
+
+        Self { value, handle }
+    }
+
+    fn run(eval: &Rc<RefCell<Eval<T>>>) {
+        let deps = {
+            let mut e = eval.borrow_mut();
+            if e.handle.upgrade().is_none() {
+                return;
+            }
+
+            let (value, deps) = {
+                let f = &mut e.f;
+                track(f)
+            };
+            // May run more than once before the next sync
+            *e.value.write_advanced() = value;
+
+            if deps == e.deps {
+                return;
+            }
+            deps
+        };
+
+        Self::subscribe(eval, deps);
+    }
+
+    fn subscribe(eval: &Rc<RefCell<Eval<T>>>, deps: Vec<usize>) {
+        let handle = match eval.borrow().handle.upgrade() {
+            Some(handle) => handle,
+            None => return,
+        };
+
+        let f = {
+            let eval = eval.clone();
+            Rc::new(RefCell::new(move || Self::run(&eval)))
+        };
+        let h = listen_ptrs(Phase::Compute, &deps, f);
+        eval.borrow_mut().deps = deps;
+
+        // Dropping the previous handle unregisters the old dependencies
+        *handle.lock().unwrap() = Some(h);
+    }
+}
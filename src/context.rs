@@ -0,0 +1,386 @@
+use super::*;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::mem;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::task::Waker;
+use std::thread::{self, ThreadId};
+
+pub const DEFAULT_MAX_NOTIFY_DEPTH: usize = 100;
+
+type NotifyCycleHook = Arc<dyn Fn(&NotifyCycle) + Send + Sync>;
+
+pub type Listeners = HashMap<usize, Vec<(ListenerHandle, Rc<RefCell<dyn FnMut()>>)>>;
+
+struct Inner {
+    readers: usize,
+    parallel_sync: AtomicBool,
+    parallel_copy_threshold: AtomicUsize,
+    strict_writes: AtomicBool,
+    listener_order: AtomicUsize,
+    max_notify_depth: AtomicUsize,
+    on_notify_cycle: Mutex<Option<NotifyCycleHook>>,
+    dirties: TrustCell<Dirties>,
+    listeners: TrustCell<Listeners>,
+    // Handles dropped away from the thread owning their listeners
+    unlistens: Vec<Mutex<Vec<ListenerHandle>>>,
+    // Thread bound to each slot, with how many of its guards are alive
+    owners: Vec<Mutex<Option<(ThreadId, usize)>>>,
+    // Times each slot was synced into, and the tasks waiting for the next one
+    syncs: Vec<Counter>,
+    // Ticks ended on each slot, see advance_tick
+    ticks: Vec<Counter>,
+}
+
+// A count which tasks can wait to change
+#[derive(Default)]
+struct Counter {
+    inner: Mutex<(usize, Vec<Waker>)>,
+}
+
+impl Counter {
+    fn get(&self) -> usize {
+        self.inner.lock().unwrap().0
+    }
+
+    // Returns whether the count changed since seen, otherwise waker is
+    // woken by the next change
+    fn wait(&self, seen: usize, waker: &Waker) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+
+        if inner.0 != seen {
+            return true;
+        }
+        if !inner.1.iter().any(|it| it.will_wake(waker)) {
+            inner.1.push(waker.clone());
+        }
+
+        false
+    }
+
+    fn bump(&self) {
+        let wakers = {
+            let mut inner = self.inner.lock().unwrap();
+            inner.0 += 1;
+            mem::take(&mut inner.1)
+        };
+
+        wakers.into_iter().for_each(|it| it.wake());
+    }
+}
+
+// Each slot of dirties and listeners is only touched by the thread owning
+// that slot (or while the other threads are parked during sync_to),
+// same as when these lived in statics
+unsafe impl Send for Inner {}
+unsafe impl Sync for Inner {}
+
+#[derive(Clone)]
+pub struct SyncContext {
+    inner: Arc<Inner>,
+}
+
+pub struct WeakSyncContext {
+    inner: Weak<Inner>,
+}
+
+// Restores the previous context of the thread which entered, so it must
+// be dropped on that same thread
+///
+/// ```compile_fail,E0277
+/// fn assert_send<T: Send>(_: T) {}
+/// let ctx = tl_sync::SyncContext::new();
+/// assert_send(ctx.enter());
+/// ```
+pub struct ContextGuard {
+    prev: Option<SyncContext>,
+    _not_send: PhantomData<*const ()>,
+}
+
+thread_local! {
+    static CURRENT_CONTEXT: RefCell<Option<SyncContext>> = const { RefCell::new(None) };
+}
+
+impl Drop for ContextGuard {
+    fn drop(&mut self) {
+        let prev = self.prev.take();
+        CURRENT_CONTEXT.with(|c| *c.borrow_mut() = prev);
+    }
+}
+
+impl Default for SyncContext {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SyncContext {
+    pub fn new() -> Self {
+        Self::with_readers(DEFAULT_READERS)
+    }
+
+    // One slot for each reader thread, plus the shared mutation slot
+    // which is always the last one
+    pub fn with_readers(readers: usize) -> Self {
+        assert!(readers > 0, "SyncContext needs at least 1 reader thread");
+        let threads = readers + 1;
+
+        Self {
+            inner: Arc::new(Inner {
+                readers,
+                parallel_sync: AtomicBool::new(false),
+                parallel_copy_threshold: AtomicUsize::new(usize::MAX),
+                strict_writes: AtomicBool::new(false),
+                listener_order: AtomicUsize::new(0),
+                max_notify_depth: AtomicUsize::new(DEFAULT_MAX_NOTIFY_DEPTH),
+                on_notify_cycle: Mutex::new(None),
+                dirties: TrustCell::new((0..threads).map(|_| Default::default()).collect()),
+                listeners: TrustCell::new((0..threads).map(|_| Default::default()).collect()),
+                unlistens: (0..threads).map(|_| Default::default()).collect(),
+                owners: (0..threads).map(|_| Default::default()).collect(),
+                syncs: (0..threads).map(|_| Default::default()).collect(),
+                ticks: (0..threads).map(|_| Default::default()).collect(),
+            }),
+        }
+    }
+
+    pub fn current() -> Self {
+        CURRENT_CONTEXT.with(|c| match *c.borrow() {
+            Some(ref ctx) => ctx.clone(),
+            None => panic!("No SyncContext entered on this thread"),
+        })
+    }
+
+    pub fn enter(&self) -> ContextGuard {
+        let prev = CURRENT_CONTEXT.with(|c| c.borrow_mut().replace(self.clone()));
+
+        ContextGuard {
+            prev,
+            _not_send: PhantomData,
+        }
+    }
+
+    pub fn is_current(&self) -> bool {
+        CURRENT_CONTEXT.with(|c| match *c.borrow() {
+            Some(ref ctx) => Arc::ptr_eq(&ctx.inner, &self.inner),
+            None => false,
+        })
+    }
+
+    pub fn downgrade(&self) -> WeakSyncContext {
+        WeakSyncContext {
+            inner: Arc::downgrade(&self.inner),
+        }
+    }
+
+    pub fn readers(&self) -> usize {
+        self.inner.readers
+    }
+
+    pub fn threads(&self) -> usize {
+        self.inner.readers + 1
+    }
+
+    pub fn mutate_index(&self) -> usize {
+        self.inner.readers
+    }
+
+    // Sync independent dirty entries on the rayon pool
+    pub fn set_parallel_sync(&self, enabled: bool) {
+        self.inner.parallel_sync.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn is_parallel_sync(&self) -> bool {
+        self.inner.parallel_sync.load(Ordering::Relaxed)
+    }
+
+    // Vec copies at least this long are split into chunks across the rayon pool
+    pub fn set_parallel_copy_threshold(&self, len: usize) {
+        self.inner.parallel_copy_threshold.store(len, Ordering::Relaxed);
+    }
+
+    pub fn parallel_copy_threshold(&self) -> usize {
+        self.inner.parallel_copy_threshold.load(Ordering::Relaxed)
+    }
+
+    // Panic when Tl::write is called twice on the same Tl before a sync,
+    // to catch accidental double writes during development
+    pub fn set_strict_writes(&self, enabled: bool) {
+        self.inner.strict_writes.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn is_strict_writes(&self) -> bool {
+        self.inner.strict_writes.load(Ordering::Relaxed)
+    }
+
+    // Notify rounds each runner tick before reporting a cycle
+    pub fn set_max_notify_depth(&self, depth: usize) {
+        assert!(depth > 0, "Max notify depth must be at least 1");
+        self.inner.max_notify_depth.store(depth, Ordering::Relaxed);
+    }
+
+    pub fn max_notify_depth(&self) -> usize {
+        self.inner.max_notify_depth.load(Ordering::Relaxed)
+    }
+
+    // Called by the runner, on the UI or compute thread, with the cycles it
+    // finds. They are ignored when no hook is set
+    pub fn set_on_notify_cycle<F: 'static + Fn(&NotifyCycle) + Send + Sync>(&self, f: F) {
+        *self.inner.on_notify_cycle.lock().unwrap() = Some(Arc::new(f));
+    }
+
+    pub fn report_notify_cycle(&self, e: &NotifyCycle) {
+        // Not called under the lock, the hook may set another one
+        let f = self.inner.on_notify_cycle.lock().unwrap().clone();
+
+        if let Some(f) = f {
+            f(e);
+        }
+    }
+
+    pub fn dirties(&self) -> &TrustCell<Dirties> {
+        &self.inner.dirties
+    }
+
+    pub fn listeners(&self) -> &TrustCell<Listeners> {
+        &self.inner.listeners
+    }
+
+    // Caller must be the thread bound to slot i, or a thread syncing into it
+    // while the owner is parked, and must not hold another reference
+    // into slot i while the returned one is alive
+    #[allow(clippy::mut_from_ref)]
+    pub(crate) unsafe fn dirties_mut(&self, i: usize) -> &mut Dirties {
+        self.inner.dirties.to_mut(i)
+    }
+
+    // Same as dirties_mut
+    #[allow(clippy::mut_from_ref)]
+    pub(crate) unsafe fn listeners_mut(&self, i: usize) -> &mut Listeners {
+        self.inner.listeners.to_mut(i)
+    }
+
+    pub(crate) fn next_listener_order(&self) -> usize {
+        self.inner.listener_order.fetch_add(1, Ordering::Relaxed)
+    }
+
+    pub(crate) fn queue_unlisten(&self, i: usize, handles: Vec<ListenerHandle>) {
+        self.inner.unlistens[i].lock().unwrap().extend(handles);
+    }
+
+    // Called by the thread owning slot i, returns whether anything was removed
+    pub(crate) fn apply_unlistens(&self, i: usize) -> bool {
+        let handles = mem::take(&mut *self.inner.unlistens[i].lock().unwrap());
+
+        if handles.is_empty() {
+            return false;
+        }
+
+        remove_listeners(unsafe { self.listeners_mut(i) }, &handles);
+        true
+    }
+
+    // Panics when another thread holds slot i, the same thread may bind again
+    pub(crate) fn bind_slot(&self, i: usize) {
+        let id = thread::current().id();
+        let bound = {
+            let mut owner = self.inner.owners[i].lock().unwrap();
+
+            match *owner {
+                Some((owner_id, ref mut count)) if owner_id == id => {
+                    *count += 1;
+                    true
+                }
+                Some(_) => false,
+                None => {
+                    *owner = Some((id, 1));
+                    true
+                }
+            }
+        };
+
+        // Outside the lock, to not poison it
+        assert!(
+            bound,
+            "Slot {} is already bound to another thread, drop its ThreadGuard first",
+            i
+        );
+    }
+
+    pub(crate) fn unbind_slot(&self, i: usize) {
+        let mut owner = self.inner.owners[i].lock().unwrap();
+        let released = match *owner {
+            Some((_, ref mut count)) => {
+                *count -= 1;
+                *count == 0
+            }
+            None => false,
+        };
+
+        if released {
+            *owner = None;
+        }
+    }
+
+    pub(crate) fn sync_count(&self, i: usize) -> usize {
+        self.inner.syncs[i].get()
+    }
+
+    // Returns whether slot i was synced since seen, otherwise waker is
+    // woken by the next sync
+    pub(crate) fn wait_sync(&self, i: usize, seen: usize, waker: &Waker) -> bool {
+        self.inner.syncs[i].wait(seen, waker)
+    }
+
+    pub(crate) fn synced(&self, i: usize) {
+        self.inner.syncs[i].bump();
+    }
+
+    pub(crate) fn tick_count(&self, i: usize) -> usize {
+        self.inner.ticks[i].get()
+    }
+
+    pub(crate) fn wait_tick(&self, i: usize, seen: usize, waker: &Waker) -> bool {
+        self.inner.ticks[i].wait(seen, waker)
+    }
+
+    pub(crate) fn ticked(&self, i: usize) {
+        self.inner.ticks[i].bump();
+    }
+
+    pub fn ensure_empty(&self) {
+        // Dropping listeners may drop handles of other slots
+        // Not any, every slot is drained each round
+        while (0..self.threads()).filter(|&i| self.apply_unlistens(i)).count() > 0 {}
+
+        let d = self.dirties();
+        let l = self.listeners();
+
+        for i in 0..self.threads() {
+            assert!(d.get(i).is_empty());
+            assert!(l.get(i).is_empty());
+        }
+    }
+}
+
+impl WeakSyncContext {
+    pub fn upgrade(&self) -> Option<SyncContext> {
+        self.inner.upgrade().map(|inner| SyncContext { inner })
+    }
+
+    // Without upgrading, the allocation stays while any weak is alive
+    // so another context cannot take its address
+    pub fn is(&self, ctx: &SyncContext) -> bool {
+        Weak::as_ptr(&self.inner) == Arc::as_ptr(&ctx.inner)
+    }
+
+    pub fn is_current(&self) -> bool {
+        CURRENT_CONTEXT.with(|c| match *c.borrow() {
+            Some(ref ctx) => self.is(ctx),
+            None => false,
+        })
+    }
+}
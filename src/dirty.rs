@@ -0,0 +1,155 @@
+use super::*;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::mem;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DirtyState {
+    // Written on this thread, waiting for sync_from
+    Mutated,
+    // Copied from the mutation slot into this thread's slot
+    Synced,
+    // Listeners on this thread have been notified, cleared at sync_clear
+    // but kept until sync_to passes it on
+    Notified,
+    // Copied into this thread's slot by another thread's sync_to
+    Received,
+    // Received and notified, will be cleared at sync_clear
+    Consumed,
+}
+
+#[derive(Debug)]
+pub struct DirtyStateError {
+    pub from: DirtyState,
+    pub to: DirtyState,
+    pub ptr: usize,
+}
+
+impl fmt::Display for DirtyStateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::DirtyState::*;
+
+        match (self.from, self.to) {
+            (Mutated, Mutated) => write!(
+                f,
+                "Tl {:#x} was mutated twice before sync, strict writes only allow one",
+                self.ptr
+            ),
+            (Received, Mutated) => write!(
+                f,
+                "Tl {:#x} was mutated after being received from another thread, \
+                 only allow mutation from 1 thread",
+                self.ptr
+            ),
+            (from, to) => write!(
+                f,
+                "Tl {:#x} cannot go from {:?} to {:?}",
+                self.ptr, from, to
+            ),
+        }
+    }
+}
+
+impl Error for DirtyStateError {}
+
+impl DirtyState {
+    pub fn is_local(&self) -> bool {
+        match *self {
+            DirtyState::Mutated | DirtyState::Synced | DirtyState::Notified => true,
+            DirtyState::Received | DirtyState::Consumed => false,
+        }
+    }
+
+    pub fn transition(self, to: DirtyState, ptr: usize) -> Result<DirtyState, DirtyStateError> {
+        use self::DirtyState::*;
+
+        match (self, to) {
+            (Synced, Mutated) | (Notified, Mutated) | (Consumed, Mutated) => Ok(to),
+            (Mutated, Synced) => Ok(to),
+            (Synced, Notified) => Ok(to),
+            (Received, Consumed) => Ok(to),
+            _ => Err(DirtyStateError { from: self, to, ptr }),
+        }
+    }
+
+    pub fn advance(&mut self, to: DirtyState, ptr: usize) {
+        match self.transition(to, ptr) {
+            Ok(s) => *self = s,
+            Err(e) => panic!("{}", e),
+        }
+    }
+}
+
+// Dirty entries of one thread slot, with an index from Tl pointer
+// to its first entry so marking dirty does not scan the whole list
+#[derive(Default)]
+pub struct Dirties {
+    entries: Vec<(DirtyState, Box<dyn Dirty>)>,
+    index: HashMap<usize, usize>,
+}
+
+impl Dirties {
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, ptr: usize) -> Option<DirtyState> {
+        self.index.get(&ptr).map(|&i| self.entries[i].0)
+    }
+
+    pub fn get_mut(&mut self, ptr: usize) -> Option<&mut DirtyState> {
+        match self.index.get(&ptr) {
+            Some(&i) => Some(&mut self.entries[i].0),
+            None => None,
+        }
+    }
+
+    pub fn push(&mut self, state: DirtyState, d: Box<dyn Dirty>) {
+        let i = self.entries.len();
+        self.index.entry(d.get_ptr()).or_insert(i);
+        self.entries.push((state, d));
+    }
+
+    pub(crate) fn mark_mutated<F>(&mut self, ptr: usize, strict: bool, make: F)
+    where
+        F: FnOnce() -> Box<dyn Dirty>,
+    {
+        match self.get_mut(ptr) {
+            Some(state) => match *state {
+                DirtyState::Mutated if !strict => (),
+                _ => state.advance(DirtyState::Mutated, ptr),
+            },
+            None => self.push(DirtyState::Mutated, make()),
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (DirtyState, &dyn Dirty)> {
+        self.entries.iter().map(|it| (it.0, &*it.1))
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&mut DirtyState, &dyn Dirty)> {
+        self.entries.iter_mut().map(|it| (&mut it.0, &*it.1))
+    }
+
+    pub fn take(&mut self) -> Vec<(DirtyState, Box<dyn Dirty>)> {
+        self.index.clear();
+        mem::take(&mut self.entries)
+    }
+
+    pub fn retain<F: FnMut(DirtyState, &dyn Dirty) -> bool>(&mut self, mut f: F) {
+        self.entries.retain(|it| f(it.0, &*it.1));
+        self.reindex();
+    }
+
+    fn reindex(&mut self) {
+        self.index.clear();
+        for (i, it) in self.entries.iter().enumerate() {
+            self.index.entry(it.1.get_ptr()).or_insert(i);
+        }
+    }
+}
@@ -1,9 +1,6 @@
-#![feature(box_into_raw_non_null)]
-#![feature(fnbox)]
-#![feature(drain_filter)]
-
+extern crate rayon;
+extern crate tl_sync_derive;
 extern crate uuid;
-// extern crate rayon;
 
 mod rc;
 pub use rc::*;
@@ -16,18 +13,46 @@ use cell::*;
 
 mod manual_copy;
 pub use manual_copy::*;
+pub use tl_sync_derive::ManualCopy;
 
 mod tl;
 pub use tl::*;
 
+mod collections;
+pub use collections::*;
+
+mod computed;
+pub use computed::*;
+
+mod dirty;
+pub use dirty::*;
+
 mod sync;
 pub use sync::*;
 
+mod transaction;
+pub use transaction::*;
+
+mod subscriptions;
+pub use subscriptions::*;
+
+mod context;
+pub use context::*;
+
 mod threads;
 pub use threads::*;
 
+mod tasks;
+pub use tasks::*;
+
+mod stream;
+pub use stream::*;
+
 mod runner;
 pub use runner::*;
 
 mod actions;
 pub use actions::*;
+
+mod request;
+pub use request::*;
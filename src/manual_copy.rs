@@ -1,45 +1,66 @@
+use rayon::prelude::*;
+use std::cell::Cell;
 use std::cmp;
-use std::time::Instant;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
 use std::hash::Hash;
-// use rayon::prelude::*;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-pub trait ManualCopy<T> {
-    fn copy_from(&mut self, &mut T);
+thread_local! {
+    // Set by sync_all from the context's setting, on each thread doing the
+    // copies, since pool threads have no current context
+    static PARALLEL_COPY_THRESHOLD: Cell<usize> = const { Cell::new(usize::MAX) };
+}
 
-    fn clear(&mut self) {
-        // Do nothing by default
-    }
+// Restores the previous threshold even when a copy panics
+struct ThresholdGuard {
+    prev: usize,
 }
 
-impl ManualCopy<u8> for u8 {
-    fn copy_from(&mut self, other: &mut u8) {
-        *self = *other;
+impl Drop for ThresholdGuard {
+    fn drop(&mut self) {
+        let prev = self.prev;
+        PARALLEL_COPY_THRESHOLD.with(|c| c.set(prev));
     }
 }
 
-impl ManualCopy<u64> for u64 {
-    fn copy_from(&mut self, other: &mut u64) {
-        *self = *other;
-    }
+pub(crate) fn with_parallel_copy_threshold<R, F: FnOnce() -> R>(len: usize, f: F) -> R {
+    let _guard = ThresholdGuard {
+        prev: PARALLEL_COPY_THRESHOLD.with(|c| c.replace(len)),
+    };
+
+    f()
 }
 
-impl ManualCopy<usize> for usize {
-    fn copy_from(&mut self, other: &mut usize) {
-        *self = *other;
+pub trait ManualCopy<T> {
+    fn copy_from(&mut self, other: &mut T);
+
+    fn clear(&mut self) {
+        // Do nothing by default
     }
-}
 
-impl ManualCopy<isize> for isize {
-    fn copy_from(&mut self, other: &mut isize) {
-        *self = *other;
+    // Called on the source slot once sync_to has copied it to every target
+    fn forwarded(&mut self) {
+        // Do nothing by default
     }
 }
 
-impl ManualCopy<Instant> for Instant {
-    fn copy_from(&mut self, other: &mut Instant) {
-        *self = *other;
-    }
+macro_rules! impl_manual_copy_for_copy {
+    ($($t:ty)*) => ($(
+        impl ManualCopy<$t> for $t {
+            fn copy_from(&mut self, other: &mut $t) {
+                *self = *other;
+            }
+        }
+    )*)
+}
+
+impl_manual_copy_for_copy! {
+    () bool char
+    u8 u16 u32 u64 u128 usize
+    i8 i16 i32 i64 i128 isize
+    f32 f64
+    Instant Duration
 }
 
 impl ManualCopy<String> for String {
@@ -51,43 +72,78 @@ impl ManualCopy<String> for String {
 
 impl<T: Clone> ManualCopy<Option<T>> for Option<T> {
     fn copy_from(&mut self, other: &mut Option<T>) {
-        *self = match *other {
-            None => None,
-            Some(ref v) => Some(v.clone()),
-        }
+        self.clone_from(other);
     }
 }
 
-impl<T1: Clone, T2: Clone> ManualCopy<(T1, T2)> for (T1, T2) {
-    fn copy_from(&mut self, other: &mut (T1, T2)) {
-        // TODO If U: copy, try to use memcpy (=)
-        self.0 = other.0.clone();
-        self.1 = other.1.clone();
+// Clone of a Copy type is a plain memcpy, so these need no special case
+macro_rules! impl_manual_copy_for_tuple {
+    ($(($($t:ident $i:tt),+))*) => ($(
+        impl<$($t: Clone),+> ManualCopy<($($t,)+)> for ($($t,)+) {
+            fn copy_from(&mut self, other: &mut ($($t,)+)) {
+                $(self.$i.clone_from(&other.$i);)+
+            }
+        }
+    )*)
+}
+
+impl_manual_copy_for_tuple! {
+    (T1 0)
+    (T1 0, T2 1)
+    (T1 0, T2 1, T3 2)
+    (T1 0, T2 1, T3 2, T4 3)
+    (T1 0, T2 1, T3 2, T4 3, T5 4)
+    (T1 0, T2 1, T3 2, T4 3, T5 4, T6 5)
+    (T1 0, T2 1, T3 2, T4 3, T5 4, T6 5, T7 6)
+    (T1 0, T2 1, T3 2, T4 3, T5 4, T6 5, T7 6, T8 7)
+    (T1 0, T2 1, T3 2, T4 3, T5 4, T6 5, T7 6, T8 7, T9 8)
+    (T1 0, T2 1, T3 2, T4 3, T5 4, T6 5, T7 6, T8 7, T9 8, T10 9)
+    (T1 0, T2 1, T3 2, T4 3, T5 4, T6 5, T7 6, T8 7, T9 8, T10 9, T11 10)
+    (T1 0, T2 1, T3 2, T4 3, T5 4, T6 5, T7 6, T8 7, T9 8, T10 9, T11 10, T12 11)
+}
+
+// clone_from_slice becomes copy_from_slice for Copy types
+impl<T: Clone, const N: usize> ManualCopy<[T; N]> for [T; N] {
+    fn copy_from(&mut self, other: &mut [T; N]) {
+        self.clone_from_slice(other);
     }
 }
 
 impl<U: Send + Sync + Clone> ManualCopy<Vec<U>> for Vec<U> {
     fn copy_from(&mut self, other: &mut Vec<U>) {
-        // TODO If U: Copy, try to use memcpy (copy_from_slice)
+        // Both clone_from_slice and extend_from_slice
+        // become memcpy when U: Copy
         let slen = self.len();
         let olen = other.len();
+        let min_len = cmp::min(slen, olen);
 
-        if slen < olen {
-            for i in slen..olen {
-                self.push(other[i].clone());
-            }
-        } else if slen > olen {
-            self.truncate(olen)
+        if slen > olen {
+            self.truncate(olen);
         }
 
-        let min_len = cmp::min(slen, olen);
-        for i in 0..min_len {
-            self[i] = other[i].clone();
+        let threshold = PARALLEL_COPY_THRESHOLD.with(|c| c.get());
+        if min_len >= threshold {
+            // A threshold of 0 would give empty chunks for short Vecs
+            let chunk = cmp::max(1, cmp::max(threshold, min_len / rayon::current_num_threads()));
+
+            self[..min_len]
+                .par_chunks_mut(chunk)
+                .zip(other[..min_len].par_chunks(chunk))
+                .for_each(|(s, o)| s.clone_from_slice(o));
+        } else {
+            self[..min_len].clone_from_slice(&other[..min_len]);
         }
-        // TODO Should use parallel to sync, if faster than single thread
-        // self.as_mut_slice()[..min_len].par_iter_mut().enumerate().for_each(|(i, it)| {
-        //     *it = other[i].clone();
-        // });
+
+        if slen < olen {
+            self.extend_from_slice(&other[slen..]);
+        }
+    }
+}
+
+impl<T: Clone> ManualCopy<VecDeque<T>> for VecDeque<T> {
+    fn copy_from(&mut self, other: &mut VecDeque<T>) {
+        self.clear();
+        self.extend(other.iter().cloned());
     }
 }
 
@@ -100,3 +156,45 @@ impl<K: Clone + Eq + Hash, V: Send + Sync + Clone> ManualCopy<HashMap<K, V>> for
         }
     }
 }
+
+impl<K: Clone + Ord, V: Clone> ManualCopy<BTreeMap<K, V>> for BTreeMap<K, V> {
+    fn copy_from(&mut self, other: &mut BTreeMap<K, V>) {
+        *self = other.clone();
+    }
+}
+
+impl<T: Clone + Eq + Hash> ManualCopy<HashSet<T>> for HashSet<T> {
+    fn copy_from(&mut self, other: &mut HashSet<T>) {
+        self.clear();
+        self.extend(other.iter().cloned());
+    }
+}
+
+impl<T: Clone + Ord> ManualCopy<BTreeSet<T>> for BTreeSet<T> {
+    fn copy_from(&mut self, other: &mut BTreeSet<T>) {
+        *self = other.clone();
+    }
+}
+
+impl<T: ManualCopy<T>> ManualCopy<Box<T>> for Box<T> {
+    fn copy_from(&mut self, other: &mut Box<T>) {
+        (**self).copy_from(&mut **other);
+    }
+
+    fn clear(&mut self) {
+        (**self).clear();
+    }
+
+    fn forwarded(&mut self) {
+        (**self).forwarded();
+    }
+}
+
+// Arc is shared, so copying only points to the same value
+impl<T> ManualCopy<Arc<T>> for Arc<T> {
+    fn copy_from(&mut self, other: &mut Arc<T>) {
+        if !Arc::ptr_eq(self, other) {
+            *self = other.clone();
+        }
+    }
+}
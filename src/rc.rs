@@ -54,12 +54,16 @@ impl<T> Wrc<T> {
     }
 
     pub fn make_strong(&self) -> Wrc<T> {
+        match self.try_make_strong() {
+            Some(s) => s,
+            None => panic!("Value already dropped"),
+        }
+    }
+
+    pub fn try_make_strong(&self) -> Option<Wrc<T>> {
         match *self {
-            Strong(ref s) => Strong(s.clone()),
-            Weak(ref w) => match w.upgrade() {
-                Some(ref s) => Strong(s.clone()),
-                None => panic!("Value already dropped"),
-            },
+            Strong(ref s) => Some(Strong(s.clone())),
+            Weak(ref w) => w.upgrade().map(Strong),
         }
     }
 }
@@ -0,0 +1,102 @@
+use super::*;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static NEXT_TICKET: AtomicUsize = AtomicUsize::new(1);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Ticket(usize);
+
+// Requests are fired like actions, whichever thread handles them replies
+// with the ticket, and replies come back to every thread on a later sync
+pub struct Request<Req, Resp, E = String> {
+    requests: Action<(Ticket, Req)>,
+    replies: Action<(Ticket, Result<Resp, E>)>,
+}
+
+impl<Req, Resp, E> Clone for Request<Req, Resp, E> {
+    fn clone(&self) -> Self {
+        Self {
+            requests: self.requests.clone(),
+            replies: self.replies.clone(),
+        }
+    }
+}
+
+impl<Req, Resp, E> Default for Request<Req, Resp, E>
+where
+    Req: 'static + Send + Sync,
+    Resp: 'static + Send + Sync,
+    E: 'static + Send + Sync,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<Req, Resp, E> Request<Req, Resp, E>
+where
+    Req: 'static + Send + Sync,
+    Resp: 'static + Send + Sync,
+    E: 'static + Send + Sync,
+{
+    pub fn new() -> Self {
+        Self {
+            requests: Action::new(),
+            replies: Action::new(),
+        }
+    }
+
+    pub fn send(&self, req: Req) -> Ticket {
+        let ticket = Ticket(NEXT_TICKET.fetch_add(1, Ordering::Relaxed));
+        self.requests.fire((ticket, req));
+
+        ticket
+    }
+
+    pub fn reply(&self, ticket: Ticket, result: Result<Resp, E>) {
+        self.replies.fire((ticket, result));
+    }
+
+    // Replies to each request with f, on the thread calling this
+    pub fn handle<F>(&self, f: F) -> ListenerHandleRef
+    where
+        F: 'static + FnMut(&Req) -> Result<Resp, E>,
+    {
+        let this = self.clone();
+        let mut f = f;
+
+        self.requests.subscribe_in(Phase::Validate, move |requests| {
+            for &(ticket, ref req) in requests.iter() {
+                this.reply(ticket, f(req));
+            }
+        })
+    }
+
+    pub fn on_reply<F>(&self, f: F) -> ListenerHandleRef
+    where
+        F: 'static + FnMut(Ticket, &Result<Resp, E>),
+    {
+        let mut f = f;
+
+        self.replies.subscribe(move |replies| {
+            for &(ticket, ref result) in replies.iter() {
+                f(ticket, result);
+            }
+        })
+    }
+
+    pub fn requests(&self) -> &Action<(Ticket, Req)> {
+        &self.requests
+    }
+
+    pub fn replies(&self) -> &Action<(Ticket, Result<Resp, E>)> {
+        &self.replies
+    }
+}
+
+impl<Req, Resp, E> ManualCopy<Request<Req, Resp, E>> for Request<Req, Resp, E> {
+    fn copy_from(&mut self, other: &mut Request<Req, Resp, E>) {
+        self.requests.copy_from(&mut other.requests);
+        self.replies.copy_from(&mut other.replies);
+    }
+}
@@ -1,5 +1,4 @@
 use super::*;
-use std::boxed::FnBox;
 use std::sync::mpsc;
 use std::thread;
 use std::time::{Duration, Instant};
@@ -18,17 +17,23 @@ pub trait ComputeSetup {
     fn setup_compute(&self);
 }
 
+// Tick to call on each UI frame, and stop to end the compute thread
+pub type Runner = (Box<dyn FnMut()>, Box<dyn FnOnce()>);
+
 pub fn setup<T: 'static + Send + Clone + UiSetup + ComputeSetup>(
+    ctx: &SyncContext,
     root: T,
     compute_update_duration: Duration,
-) -> (Box<FnMut()>, Box<FnBox()>) {
-    init_dirties();
+) -> Runner {
+    assert!(ctx.readers() >= 2, "Runner needs both UI and compute slots");
+    let mutate_index = ctx.mutate_index();
 
     let (compute_tx, compute_rx) = mpsc::channel();
     let compute_rtx: mpsc::Sender<bool>;
 
     let compute_thread = {
         let root = root.clone();
+        let ctx = ctx.clone();
         let (tx, rx) = mpsc::channel();
         compute_rtx = tx.clone();
         let tx = compute_tx.clone();
@@ -36,15 +41,19 @@ pub fn setup<T: 'static + Send + Clone + UiSetup + ComputeSetup>(
         thread::Builder::new()
             .name("1_compute".into())
             .spawn(move || {
+                let _thread = ctx.register_thread(COMPUTE_THREAD);
                 root.setup_compute();
                 loop {
-                    let mut still_dirty = true;
-                    let now = Instant::now();
-                    while still_dirty && now.elapsed() < compute_update_duration {
-                        sync_from(2);
-                        still_dirty = peek_notify(prepare_peek_notify()) > 0;
-                        sync_clear();
+                    // What is left stays dirty for the next tick
+                    if let Err(e) = notify_until_stable_within(
+                        mutate_index,
+                        ctx.max_notify_depth(),
+                        compute_update_duration,
+                    ) {
+                        ctx.report_notify_cycle(&e);
                     }
+                    run_tasks();
+                    advance_tick();
 
                     match tx.send(SyncStatus::Idle) {
                         Ok(_) => (),
@@ -54,7 +63,7 @@ pub fn setup<T: 'static + Send + Clone + UiSetup + ComputeSetup>(
                         Ok(true) => (),
                         _ => break,
                     }
-                    sync_to(0);
+                    sync_to(UI_THREAD);
                     match tx.send(SyncStatus::JustSync) {
                         Ok(_) => (),
                         _ => break,
@@ -65,40 +74,54 @@ pub fn setup<T: 'static + Send + Clone + UiSetup + ComputeSetup>(
                         _ => break,
                     }
                 }
+                clear_tasks();
+                sync_clear();
             }).unwrap()
     };
 
-    root.setup_ui();
+    {
+        let _thread = ctx.register_thread(UI_THREAD);
+        root.setup_ui();
+    }
 
     let stop = Box::new({
+        let ctx = ctx.clone();
         let compute_rtx = compute_rtx.clone();
 
         move || {
             compute_rtx.send(false).unwrap();
             compute_thread.join().unwrap();
 
+            let _thread = ctx.register_thread(UI_THREAD);
+            clear_tasks();
             prepare_peek_notify();
-            ensure_empty_dirties();
-            drop_dirties();
+            ctx.ensure_empty();
         }
     });
 
+    let ctx = ctx.clone();
     let mut just_sync = false;
 
     let tick = Box::new(move || {
+        let _thread = ctx.register_thread(UI_THREAD);
         let now = Instant::now();
 
-        sync_from(2);
+        sync_from(mutate_index);
         let prepared = prepare_peek_notify();
 
         if just_sync {
             just_sync = false;
-            sync_to(1);
+            sync_to(COMPUTE_THREAD);
             compute_rtx.send(true).unwrap();
         }
 
         peek_notify(prepared);
         sync_clear();
+        if let Err(e) = notify_until_stable(mutate_index, ctx.max_notify_depth()) {
+            ctx.report_notify_cycle(&e);
+        }
+        run_tasks();
+        advance_tick();
 
         let ui_elapsed = now.elapsed();
         if ui_elapsed > compute_update_duration {
@@ -0,0 +1,270 @@
+use super::*;
+use std::cell::RefCell;
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+
+// Same shape as the futures crate Stream, items come from listeners on the
+// thread creating the stream, so they are seen once per notify at most
+pub trait Stream {
+    type Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>>;
+
+    fn next(&mut self) -> StreamNext<'_, Self>
+    where
+        Self: Sized + Unpin,
+    {
+        StreamNext { stream: self }
+    }
+
+    // Runs f for every item until the stream ends, to pass to spawn_local
+    fn for_each<F: FnMut(Self::Item)>(self, f: F) -> ForEach<Self, F>
+    where
+        Self: Sized,
+    {
+        ForEach { stream: self, f }
+    }
+
+    // The latest item, once no other came for ticks ticks of this thread
+    fn debounce(self, ticks: usize) -> Debounce<Self>
+    where
+        Self: Sized,
+    {
+        Debounce {
+            stream: self,
+            ticks,
+            latest: None,
+            ticker: Ticker::current(),
+        }
+    }
+
+    // The first item, then nothing until ticks ticks of this thread passed
+    fn throttle(self, ticks: usize) -> Throttle<Self>
+    where
+        Self: Sized,
+    {
+        Throttle {
+            stream: self,
+            ticks,
+            last: None,
+            ticker: Ticker::current(),
+        }
+    }
+
+    // Skips items equal to the one before
+    fn distinct(self) -> Distinct<Self>
+    where
+        Self: Sized,
+        Self::Item: Clone + PartialEq,
+    {
+        Distinct {
+            stream: self,
+            last: None,
+        }
+    }
+}
+
+// Counts advance_tick calls of the thread creating it
+struct Ticker {
+    ctx: SyncContext,
+    index: usize,
+}
+
+impl Ticker {
+    fn current() -> Self {
+        Self {
+            ctx: SyncContext::current(),
+            index: thread_index(),
+        }
+    }
+
+    fn now(&self) -> usize {
+        self.ctx.tick_count(self.index)
+    }
+
+    fn wake_next(&self, now: usize, cx: &mut Context) {
+        if self.ctx.wait_tick(self.index, now, cx.waker()) {
+            cx.waker().wake_by_ref();
+        }
+    }
+}
+
+// Values read from a Tl at each of its notifies, only the latest is kept
+pub struct Snapshots<T> {
+    signal: Rc<RefCell<Signal<T>>>,
+    _handle: ListenerHandleRef,
+}
+
+impl<T: 'static> Snapshots<T> {
+    pub(crate) fn new<F: 'static + FnMut() -> T>(ptr: usize, read: F) -> Self {
+        let signal = Signal::new();
+        let mut read = read;
+        let handle = listen_ptrs(
+            Phase::default(),
+            &[ptr],
+            Rc::new(RefCell::new({
+                let signal = signal.clone();
+                move || {
+                    let mut signal = signal.borrow_mut();
+                    signal.value = Some(read());
+                    signal.wake();
+                }
+            })),
+        );
+
+        Self {
+            signal,
+            _handle: handle,
+        }
+    }
+}
+
+impl<T> Stream for Snapshots<T> {
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<T>> {
+        self.signal.borrow_mut().poll(cx).map(Some)
+    }
+}
+
+pub struct StreamNext<'a, S: 'a> {
+    stream: &'a mut S,
+}
+
+impl<'a, S: Stream + Unpin> Future for StreamNext<'a, S> {
+    type Output = Option<S::Item>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<S::Item>> {
+        Pin::new(&mut *self.stream).poll_next(cx)
+    }
+}
+
+pub struct ForEach<S, F> {
+    stream: S,
+    f: F,
+}
+
+// Neither field is pinned
+impl<S: Unpin, F> Unpin for ForEach<S, F> {}
+
+impl<S: Stream + Unpin, F: FnMut(S::Item)> Future for ForEach<S, F> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        let this = self.get_mut();
+
+        loop {
+            match Pin::new(&mut this.stream).poll_next(cx) {
+                Poll::Ready(Some(item)) => (this.f)(item),
+                Poll::Ready(None) => return Poll::Ready(()),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+pub struct Debounce<S: Stream> {
+    stream: S,
+    ticks: usize,
+    latest: Option<(S::Item, usize)>,
+    ticker: Ticker,
+}
+
+impl<S: Stream + Unpin> Unpin for Debounce<S> {}
+
+impl<S: Stream + Unpin> Stream for Debounce<S> {
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<S::Item>> {
+        let this = self.get_mut();
+        let now = this.ticker.now();
+
+        loop {
+            match Pin::new(&mut this.stream).poll_next(cx) {
+                Poll::Ready(Some(item)) => this.latest = Some((item, now)),
+                // Flush what is left
+                Poll::Ready(None) => return Poll::Ready(this.latest.take().map(|it| it.0)),
+                Poll::Pending => break,
+            }
+        }
+
+        match this.latest.take() {
+            Some((item, at)) => {
+                if now - at >= this.ticks {
+                    return Poll::Ready(Some(item));
+                }
+
+                this.latest = Some((item, at));
+                this.ticker.wake_next(now, cx);
+                Poll::Pending
+            }
+            None => Poll::Pending,
+        }
+    }
+}
+
+pub struct Throttle<S> {
+    stream: S,
+    ticks: usize,
+    last: Option<usize>,
+    ticker: Ticker,
+}
+
+impl<S: Stream + Unpin> Stream for Throttle<S> {
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<S::Item>> {
+        let this = self.get_mut();
+
+        loop {
+            match Pin::new(&mut this.stream).poll_next(cx) {
+                Poll::Ready(Some(item)) => {
+                    let now = this.ticker.now();
+                    match this.last {
+                        Some(last) if now - last < this.ticks => continue,
+                        _ => {
+                            this.last = Some(now);
+                            return Poll::Ready(Some(item));
+                        }
+                    }
+                }
+                other => return other,
+            }
+        }
+    }
+}
+
+pub struct Distinct<S: Stream> {
+    stream: S,
+    last: Option<S::Item>,
+}
+
+impl<S: Stream + Unpin> Unpin for Distinct<S> {}
+
+impl<S> Stream for Distinct<S>
+where
+    S: Stream + Unpin,
+    S::Item: Clone + PartialEq,
+{
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<S::Item>> {
+        let this = self.get_mut();
+
+        loop {
+            match Pin::new(&mut this.stream).poll_next(cx) {
+                Poll::Ready(Some(item)) => {
+                    if this.last.as_ref() == Some(&item) {
+                        continue;
+                    }
+
+                    this.last = Some(item.clone());
+                    return Poll::Ready(Some(item));
+                }
+                other => return other,
+            }
+        }
+    }
+}
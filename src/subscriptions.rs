@@ -0,0 +1,106 @@
+use super::*;
+use std::mem;
+use std::sync::Mutex;
+
+// Owns listener handles of a component, dropping the last strong clone
+// (or calling clear) unsubscribes them and those of its children,
+// even when the children are still held elsewhere
+#[derive(Clone, Default)]
+pub struct Subscriptions {
+    group: Wrc<Mutex<Group>>,
+}
+
+#[derive(Default)]
+struct Group {
+    handles: Vec<ListenerHandleRef>,
+    children: Vec<Subscriptions>,
+}
+
+impl Drop for Group {
+    fn drop(&mut self) {
+        // Child components keep strong clones of their own
+        for it in self.children.iter() {
+            it.clear();
+        }
+    }
+}
+
+impl Subscriptions {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    // For capturing inside listeners without keeping them alive
+    pub fn clone_weak(&self) -> Self {
+        Self {
+            group: self.group.clone_weak(),
+        }
+    }
+
+    pub fn be_weak(&mut self) {
+        self.group.be_weak();
+    }
+
+    pub fn is_alive(&self) -> bool {
+        self.group.try_make_strong().is_some()
+    }
+
+    // Handles added after the owner is gone are dropped right away
+    pub fn add(&self, h: ListenerHandleRef) {
+        if let Some(group) = self.group.try_make_strong() {
+            group.lock().unwrap().handles.push(h);
+        }
+    }
+
+    pub fn listen<F: 'static + FnMut()>(&self, sources: &[&dyn GetPtr], f: F) {
+        self.add(register_listener(sources, f));
+    }
+
+    pub fn listen_in<F: 'static + FnMut()>(&self, phase: Phase, sources: &[&dyn GetPtr], f: F) {
+        self.add(register_listener_in(phase, sources, f));
+    }
+
+    // Cleared together with this one
+    pub fn child(&self) -> Subscriptions {
+        let child = Subscriptions::new();
+
+        if let Some(group) = self.group.try_make_strong() {
+            group.lock().unwrap().children.push(child.clone());
+        }
+
+        child
+    }
+
+    pub fn len(&self) -> usize {
+        match self.group.try_make_strong() {
+            Some(group) => {
+                let g = group.lock().unwrap();
+                g.handles.len() + g.children.iter().map(|it| it.len()).sum::<usize>()
+            }
+            None => 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn clear(&self) {
+        let (handles, children) = match self.group.try_make_strong() {
+            Some(group) => {
+                let mut g = group.lock().unwrap();
+                (
+                    mem::take(&mut g.handles),
+                    mem::take(&mut g.children),
+                )
+            }
+            None => return,
+        };
+
+        // Outside the lock, dropping a handle may run other code
+        drop(handles);
+        for it in children.iter() {
+            it.clear();
+        }
+    }
+}
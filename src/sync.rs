@@ -1,289 +1,403 @@
 use super::*;
+use rayon::prelude::*;
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::mem;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
 use uuid::Uuid;
 
 pub trait GetPtr {
     fn get_ptr(&self) -> usize;
 }
 
-pub trait Dirty: GetPtr {
+pub trait Dirty: GetPtr + Send + Sync {
+    // Panics when not built for ctx's slot layout
+    fn check_context(&self, _ctx: &SyncContext) {}
     fn sync(&self, from: usize, to: usize);
     fn clear(&self, to: usize);
+    fn forwarded(&self, from: usize);
     fn re_add(&self);
+    fn boxed(&self) -> Box<dyn Dirty>;
 }
 
+// Within one peek_notify, listeners run by phase, then by registration order
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(Default)]
+pub enum Phase {
+    Validate,
+    #[default]
+    Compute,
+    Layout,
+    Render,
+}
+
+
+#[derive(Debug)]
+pub struct NotifyCycle {
+    pub depth: usize,
+    // Tls still dirty after the last round, most re-dirtied first
+    pub ptrs: Vec<(usize, usize)>,
+}
+
+impl fmt::Display for NotifyCycle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Listeners kept dirtying Tl(s) after {} notify rounds:", self.depth)?;
+        for &(ptr, count) in self.ptrs.iter() {
+            write!(f, " {:#x} ({} times)", ptr, count)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Error for NotifyCycle {}
+
 #[derive(Clone)]
 pub struct ListenerHandle {
     ptr: usize,
     uuid: Uuid,
+    phase: Phase,
+    order: usize,
 }
 
 pub struct ListenerHandleRef {
     pub handles: Vec<ListenerHandle>,
     from: usize,
+    ctx: WeakSyncContext,
 }
 
 impl Drop for ListenerHandleRef {
     fn drop(&mut self) {
-        // TODO Maybe drop at different thread
-        // accessing listeners here is not thread-safe
-        let l = get_listeners().to_mut(self.from);
+        let ctx = match self.ctx.upgrade() {
+            Some(ctx) => ctx,
+            None => return,
+        };
+        let handles = mem::take(&mut self.handles);
+
+        if ctx.is_current() && current_thread_index() == Some(self.from) {
+            remove_listeners(unsafe { ctx.listeners_mut(self.from) }, &handles);
+        } else {
+            // Removed by the owning thread at its next peek_notify or sync_clear
+            ctx.queue_unlisten(self.from, handles);
+        }
+    }
+}
 
-        for handle in self.handles.iter() {
-            let mut is_zeroed = false;
+pub(crate) fn remove_listeners(l: &mut Listeners, handles: &[ListenerHandle]) {
+    // Dropped after the map is updated, they may drop other handles
+    let mut removed = vec![];
 
-            if let Some(l) = l.get_mut(&handle.ptr) {
-                l.retain(|it| it.0.uuid != handle.uuid);
-                is_zeroed = l.len() == 0;
-            }
+    for handle in handles.iter() {
+        let mut is_zeroed = false;
 
-            if is_zeroed {
-                l.remove(&handle.ptr);
-            }
+        if let Some(l) = l.get_mut(&handle.ptr) {
+            let (keep, gone): (Vec<_>, Vec<_>) = mem::take(l)
+                .into_iter()
+                .partition(|it| it.0.uuid != handle.uuid);
+            *l = keep;
+            removed.push(gone);
+            is_zeroed = l.is_empty();
+        }
+
+        if is_zeroed {
+            l.remove(&handle.ptr);
         }
     }
+
+    drop(removed);
 }
 
-pub fn register_listener_1<T1, F>(t1: &T1, mut f: F) -> ListenerHandleRef
+pub fn register_listener<F>(sources: &[&dyn GetPtr], f: F) -> ListenerHandleRef
 where
-    T1: GetPtr,
-    F: 'static + FnMut() + Clone,
+    F: 'static + FnMut(),
 {
-    let uuid = Uuid::new_v4();
+    register_listener_in(Phase::default(), sources, f)
+}
 
-    let h1 = {
-        let l = get_listeners().to_mut(thread_index());
-        let ptr1 = t1.get_ptr();
-        if !l.contains_key(&ptr1) {
-            l.insert(ptr1, vec![]);
-        }
+pub fn register_listener_in<F>(phase: Phase, sources: &[&dyn GetPtr], f: F) -> ListenerHandleRef
+where
+    F: 'static + FnMut(),
+{
+    let ptrs: Vec<usize> = sources.iter().map(|it| it.get_ptr()).collect();
+    let f = Rc::new(RefCell::new(f));
+    let ret = listen_ptrs(phase, &ptrs, f.clone());
 
-        let l = l.get_mut(&ptr1).unwrap();
-        let h = ListenerHandle { ptr: ptr1, uuid };
-        l.push((h.clone(), Box::new(f.clone())));
+    (*f.borrow_mut())();
 
-        h
-    };
+    ret
+}
 
-    f();
+// Shared by every pointer, peek_notify calls it once per notify by uuid
+pub(crate) fn listen_ptrs(
+    phase: Phase,
+    ptrs: &[usize],
+    f: Rc<RefCell<dyn FnMut()>>,
+) -> ListenerHandleRef {
+    let ctx = SyncContext::current();
+    let from = thread_index();
+    let uuid = Uuid::new_v4();
+    let order = ctx.next_listener_order();
+    let l = unsafe { ctx.listeners_mut(from) };
+    let mut handles = vec![];
+
+    for &ptr in ptrs.iter() {
+        let h = ListenerHandle {
+            ptr,
+            uuid,
+            phase,
+            order,
+        };
+
+        l.entry(ptr)
+            .or_insert_with(Vec::new)
+            .push((h.clone(), f.clone()));
+        handles.push(h);
+    }
 
     ListenerHandleRef {
-        handles: vec![h1],
-        from: thread_index(),
+        handles,
+        from,
+        ctx: ctx.downgrade(),
     }
 }
 
-pub fn register_listener_2<T1, T2, F>(t1: &T1, t2: &T2, mut f: F) -> ListenerHandleRef
+pub fn register_listener_1<T1, F>(t1: &T1, f: F) -> ListenerHandleRef
 where
     T1: GetPtr,
-    T2: GetPtr,
-    F: 'static + FnMut() + Clone,
+    F: 'static + FnMut(),
 {
-    let uuid = Uuid::new_v4();
+    register_listener(&[t1], f)
+}
 
-    let h1 = {
-        let l = get_listeners().to_mut(thread_index());
-        let ptr1 = t1.get_ptr();
-        if !l.contains_key(&ptr1) {
-            l.insert(ptr1, vec![]);
-        }
+pub fn register_listener_2<T1, T2, F>(t1: &T1, t2: &T2, f: F) -> ListenerHandleRef
+where
+    T1: GetPtr,
+    T2: GetPtr,
+    F: 'static + FnMut(),
+{
+    register_listener(&[t1, t2], f)
+}
 
-        let l = l.get_mut(&ptr1).unwrap();
-        let h = ListenerHandle { ptr: ptr1, uuid };
-        l.push((h.clone(), Box::new(f.clone())));
+pub fn sync_to(to: usize) {
+    sync_to_many(&[to]);
+}
 
-        h
-    };
+pub fn sync_to_many(tos: &[usize]) {
+    let ctx = SyncContext::current();
+    let from = thread_index();
+    let mut tmp = unsafe { ctx.dirties_mut(from) }.take();
 
-    let h2 = {
-        let l = get_listeners().to_mut(thread_index());
-        let ptr2 = t2.get_ptr();
-        if !l.contains_key(&ptr2) {
-            l.insert(ptr2, vec![]);
+    tmp.retain(|it| it.0.is_local());
+    {
+        let ds: Vec<&dyn Dirty> = tmp.iter().map(|it| &*it.1).collect();
+        for &to in tos {
+            sync_all(&ctx, &ds, from, to);
         }
-
-        let l = l.get_mut(&ptr2).unwrap();
-        let h = ListenerHandle { ptr: ptr2, uuid };
-        l.push((h.clone(), Box::new(f.clone())));
-
-        h
-    };
-
-    f();
-
-    ListenerHandleRef {
-        handles: vec![h1, h2],
-        from: thread_index(),
     }
-}
-
-// TODO Use context instead of static
-static mut DIRTIES: Option<TrustCell<Vec<(u8, Box<Dirty>)>>> = None;
-static mut LISTENERS: Option<TrustCell<HashMap<usize, Vec<(ListenerHandle, Box<FnMut()>)>>>> = None;
+    // println!("SYNC {} -> {:?} : {}", from, tos, tmp.len());
+
+    tmp.iter().for_each(|it| {
+        it.1.forwarded(from);
+        match it.0 {
+            DirtyState::Mutated => it.1.re_add(),
+            // Still cleared on this thread at sync_clear, but not passed on again
+            DirtyState::Notified => unsafe { ctx.dirties_mut(from) }
+                .push(DirtyState::Consumed, it.1.boxed()),
+            _ => (),
+        }
+    });
 
-pub fn init_dirties() {
-    unsafe {
-        DIRTIES = Some(TrustCell::new(Default::default()));
-        LISTENERS = Some(TrustCell::new(Default::default()));
+    for &to in tos {
+        // Its owner is parked while this thread syncs into it
+        let dt = unsafe { ctx.dirties_mut(to) };
+        for it in tmp.iter() {
+            dt.push(DirtyState::Received, it.1.boxed());
+        }
+        ctx.synced(to);
     }
 }
 
-pub fn ensure_empty_dirties() {
-    let d = get_dirties();
-    let l = get_listeners();
+pub fn sync_from(from: usize) {
+    let ctx = SyncContext::current();
+    let to = thread_index();
+    let dt = unsafe { ctx.dirties_mut(to) };
+    let mut ds = vec![];
 
-    for i in 0..THREADS {
-        assert!(d.get(i).len() == 0);
-        assert!(l.get(i).len() == 0);
-    }
-}
+    for (state, d) in dt.iter_mut() {
+        if *state != DirtyState::Mutated {
+            continue;
+        }
+        state.advance(DirtyState::Synced, d.get_ptr());
 
-pub fn drop_dirties() {
-    // let d = get_dirties();
-    // let l = get_listeners();
-
-    // println!();
-    // println!(
-    //     "DROP DIRTIES {} {} {}",
-    //     d.get(0).len(),
-    //     d.get(1).len(),
-    //     d.get(2).len()
-    // );
-    // println!(
-    //     "DROP LISTENERS {} {} {}",
-    //     l.get(0).len(),
-    //     l.get(1).len(),
-    //     l.get(2).len()
-    // );
-    // println!();
-
-    unsafe {
-        DIRTIES = None;
-        LISTENERS = None;
+        ds.push(d);
     }
+
+    sync_all(&ctx, &ds, from, to);
+    ctx.synced(to);
+    // println!("SYNC {} <- {} : {}", to, from, ds.len());
 }
 
-pub fn get_dirties<'a>() -> &'a TrustCell<Vec<(u8, Box<Dirty>)>> {
-    unsafe {
-        match DIRTIES {
-            Some(ref d) => d,
-            None => panic!("Uninitialized DIRTIES"),
-        }
-    }
+// Ends a frame of this thread for tick based stream adapters, a tick may
+// sync many times. The runner calls it once per tick on each thread
+pub fn advance_tick() {
+    let ctx = SyncContext::current();
+    ctx.ticked(thread_index());
 }
 
-pub fn get_listeners<'a>() -> &'a TrustCell<HashMap<usize, Vec<(ListenerHandle, Box<FnMut()>)>>> {
-    unsafe {
-        match LISTENERS {
-            Some(ref l) => l,
-            None => panic!("Uninitialized LISTENERS"),
-        }
+fn sync_all(ctx: &SyncContext, ds: &[&dyn Dirty], from: usize, to: usize) {
+    // Before going to the pool, which has no current context
+    ds.iter().for_each(|d| d.check_context(ctx));
+
+    let threshold = ctx.parallel_copy_threshold();
+
+    if ctx.is_parallel_sync() && ds.len() > 1 {
+        ds.par_iter()
+            .for_each(|d| with_parallel_copy_threshold(threshold, || d.sync(from, to)));
+    } else {
+        with_parallel_copy_threshold(threshold, || ds.iter().for_each(|d| d.sync(from, to)));
     }
 }
 
-pub fn sync_to(to: usize) {
-    let from = thread_index();
-    let df = get_dirties().to_mut(from);
+pub fn peek_notify(d: Vec<usize>) -> usize {
+    let ctx = SyncContext::current();
+    let to = thread_index();
+    let mut uuids = vec![];
+    let mut fs = vec![];
 
-    let mut tmp = vec![];
-    tmp.append(df);
+    ctx.apply_unlistens(to);
 
-    // let mut v = vec![];
-    tmp.iter_mut().for_each(|it| {
-        if it.0 >= 4 {
-            return;
+    // println!("PEEK NOTIFY -> {} : {:?}", to, d);
+    {
+        let l = unsafe { ctx.listeners_mut(to) };
+        for ptr in d.iter() {
+            if let Some(l) = l.get(ptr) {
+                for it in l.iter() {
+                    let uuid = it.0.uuid;
+                    if uuids.contains(&uuid) {
+                        continue;
+                    }
+                    uuids.push(uuid);
+
+                    fs.push((it.0.phase, it.0.order, it.1.clone()));
+                }
+            }
         }
+    }
+    fs.sort_by_key(|it| (it.0, it.1));
 
-        it.1.sync(from, to);
+    // Listeners may register or drop listeners, so call them outside the map
+    for (_, _, f) in fs {
+        (*f.borrow_mut())();
+    }
 
-        if it.0 == 1 {
-            it.1.re_add();
-        }
+    d.len()
+}
 
-        it.0 = 2;
-        // v.push(it.1.get_ptr());
-    });
-    tmp.retain(|it| it.0 < 4);
-    tmp.iter_mut().for_each(|it| it.0 = 4);
-    // println!("SYNC {} -> {} : {:?}", from, to, v);
+// Sync from and notify this thread in rounds, until listeners stop
+// dirtying Tls or max_depth rounds have run. Returns the notified count
+pub fn notify_until_stable(from: usize, max_depth: usize) -> Result<usize, NotifyCycle> {
+    notify_rounds(from, max_depth, None)
+}
 
-    let dt = get_dirties().to_mut(to);
-    dt.append(&mut tmp);
+// Same, but no round starts after budget has passed. What is left then
+// stays dirty for the next call and is not reported as a cycle
+pub fn notify_until_stable_within(
+    from: usize,
+    max_depth: usize,
+    budget: Duration,
+) -> Result<usize, NotifyCycle> {
+    notify_rounds(from, max_depth, Some(Instant::now() + budget))
 }
 
-pub fn sync_from(from: usize) {
+fn notify_rounds(
+    from: usize,
+    max_depth: usize,
+    deadline: Option<Instant>,
+) -> Result<usize, NotifyCycle> {
+    let ctx = SyncContext::current();
     let to = thread_index();
-    let dt = get_dirties().to_mut(to);
+    let mut total = 0;
+    let mut counts: HashMap<usize, usize> = HashMap::new();
 
-    // let mut v = vec![];
-    for it in dt.iter_mut() {
-        if it.0 != 1 {
-            continue;
-        } else {
-            it.0 = 2;
+    for _ in 0..max_depth {
+        if deadline.is_some_and(|it| Instant::now() >= it) {
+            return Ok(total);
+        }
+
+        sync_from(from);
+        let d = prepare_peek_notify();
+        if d.is_empty() {
+            sync_clear();
+            return Ok(total);
         }
 
-        it.1.sync(from, to);
-        // v.push(it.1.get_ptr() as usize);
+        for &ptr in d.iter() {
+            *counts.entry(ptr).or_insert(0) += 1;
+        }
+        total += peek_notify(d);
+        sync_clear();
     }
-    // println!("SYNC {} <- {} : {:?}", to, from, v);
-}
 
-pub fn peek_notify(d: Vec<usize>) -> usize {
-    let to = thread_index();
-    let l = get_listeners().to_mut(to);
-    let mut uuids = vec![];
+    let mut ptrs: Vec<(usize, usize)> = unsafe { ctx.dirties_mut(to) }
+        .iter()
+        .filter(|it| it.0 == DirtyState::Mutated)
+        .map(|it| {
+            let ptr = it.1.get_ptr();
+            (ptr, counts.get(&ptr).cloned().unwrap_or(0))
+        }).collect();
 
-    // println!("PEEK NOTIFY -> {} : {:?}", to, d);
-    for ptr in d.iter() {
-        if let Some(l) = l.get_mut(&ptr) {
-            l.iter_mut().for_each(|it| {
-                let uuid = it.0.uuid;
-                if uuids.contains(&uuid) {
-                    return;
-                }
-                uuids.push(uuid);
-                
-                it.1();
-            });
-        }
+    if ptrs.is_empty() {
+        return Ok(total);
     }
+    ptrs.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
 
-    d.len()
+    Err(NotifyCycle {
+        depth: max_depth,
+        ptrs,
+    })
 }
 
 pub fn prepare_peek_notify() -> Vec<usize> {
+    let ctx = SyncContext::current();
     let to = thread_index();
-    let d = get_dirties().to_mut(to);
+    let d = unsafe { ctx.dirties_mut(to) };
     let mut tmp = vec![];
 
-    for it in d.iter_mut() {
-        if it.0 != 4 {
-            if it.0 != 2 {
-                continue;
-            } else {
-                it.0 = 3;
-            }
-        } else {
-            it.0 = 5;
+    for (state, d) in d.iter_mut() {
+        let ptr = d.get_ptr();
+
+        match *state {
+            DirtyState::Synced => state.advance(DirtyState::Notified, ptr),
+            DirtyState::Received => state.advance(DirtyState::Consumed, ptr),
+            _ => continue,
         }
 
-        tmp.push(it.1.get_ptr());
+        tmp.push(ptr);
     }
 
     tmp
 }
 
 pub fn sync_clear() {
+    let ctx = SyncContext::current();
     let to = thread_index();
-    let d = get_dirties().to_mut(to);
+    ctx.apply_unlistens(to);
 
-    d.retain(|it| {
-        if it.0 == 5 {
-            it.1.clear(to);
+    let d = unsafe { ctx.dirties_mut(to) };
+
+    // Notified entries stay until sync_to passes them on
+    d.retain(|state, d| match state {
+        DirtyState::Consumed => {
+            d.clear(to);
             false
-        } else {
+        }
+        DirtyState::Notified => {
+            d.clear(to);
             true
         }
+        _ => true,
     });
 }
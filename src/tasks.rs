@@ -0,0 +1,178 @@
+use super::*;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::future::Future;
+use std::mem;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Wake, Waker};
+
+// Set by a listener, the waiting future resolves with the value
+pub(crate) struct Signal<V> {
+    pub value: Option<V>,
+    waker: Option<Waker>,
+}
+
+impl<V> Signal<V> {
+    pub fn new() -> Rc<RefCell<Self>> {
+        Rc::new(RefCell::new(Self {
+            value: None,
+            waker: None,
+        }))
+    }
+
+    pub fn wake(&mut self) {
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+
+    pub fn poll(&mut self, cx: &mut Context) -> Poll<V> {
+        match self.value.take() {
+            Some(value) => Poll::Ready(value),
+            None => {
+                self.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+// Resolves at the first notify of this thread for ptr after it is created
+pub struct Changed {
+    signal: Rc<RefCell<Signal<()>>>,
+    _handle: ListenerHandleRef,
+}
+
+impl Changed {
+    pub(crate) fn new(ptr: usize) -> Self {
+        let signal = Signal::new();
+        let handle = listen_ptrs(
+            Phase::default(),
+            &[ptr],
+            Rc::new(RefCell::new({
+                let signal = signal.clone();
+                move || {
+                    let mut signal = signal.borrow_mut();
+                    signal.value = Some(());
+                    signal.wake();
+                }
+            })),
+        );
+
+        Self {
+            signal,
+            _handle: handle,
+        }
+    }
+}
+
+impl Future for Changed {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        self.signal.borrow_mut().poll(cx)
+    }
+}
+
+// Resolves once the slot of this thread is synced into, by sync_from
+// on this thread or sync_to from another one
+pub struct NextSync {
+    ctx: SyncContext,
+    index: usize,
+    seen: usize,
+}
+
+pub fn next_sync() -> NextSync {
+    let ctx = SyncContext::current();
+    let index = thread_index();
+    let seen = ctx.sync_count(index);
+
+    NextSync { ctx, index, seen }
+}
+
+impl Future for NextSync {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        if self.ctx.wait_sync(self.index, self.seen, cx.waker()) {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+struct Tasks {
+    next_id: usize,
+    futures: HashMap<usize, Pin<Box<dyn Future<Output = ()>>>>,
+    // Woken from any thread, polled by the owning one
+    ready: Arc<Mutex<Vec<usize>>>,
+}
+
+thread_local!(static TASKS: RefCell<Tasks> = RefCell::new(Tasks {
+    next_id: 0,
+    futures: HashMap::new(),
+    ready: Default::default(),
+}));
+
+struct TaskWaker {
+    id: usize,
+    ready: Arc<Mutex<Vec<usize>>>,
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.ready.lock().unwrap().push(self.id);
+    }
+}
+
+// The task is first polled by the next run_tasks on this thread
+pub fn spawn_local<F: 'static + Future<Output = ()>>(f: F) {
+    TASKS.with(|t| {
+        let mut t = t.borrow_mut();
+        let id = t.next_id;
+        t.next_id += 1;
+        t.futures.insert(id, Box::pin(f));
+        t.ready.lock().unwrap().push(id);
+    });
+}
+
+// Polls the tasks of this thread woken since the last run once, tasks woken
+// while running wait for the next run. Returns how many are still pending
+pub fn run_tasks() -> usize {
+    let ready = TASKS.with(|t| t.borrow().ready.clone());
+    let mut ids = mem::take(&mut *ready.lock().unwrap());
+    ids.sort();
+    ids.dedup();
+
+    for id in ids {
+        // Already done, tasks may also spawn or finish others
+        let mut f = match TASKS.with(|t| t.borrow_mut().futures.remove(&id)) {
+            Some(f) => f,
+            None => continue,
+        };
+        let waker = Waker::from(Arc::new(TaskWaker {
+            id,
+            ready: ready.clone(),
+        }));
+
+        if f.as_mut().poll(&mut Context::from_waker(&waker)).is_pending() {
+            TASKS.with(|t| t.borrow_mut().futures.insert(id, f));
+        }
+    }
+
+    TASKS.with(|t| t.borrow().futures.len())
+}
+
+// Drops every pending task of this thread, with the listeners they hold
+pub fn clear_tasks() {
+    let futures = TASKS.with(|t| {
+        let mut t = t.borrow_mut();
+        t.ready.lock().unwrap().clear();
+        mem::take(&mut t.futures)
+    });
+
+    drop(futures);
+}
@@ -1,18 +1,99 @@
+use super::*;
+use std::cell::Cell;
+use std::error::Error;
+use std::fmt;
+use std::marker::PhantomData;
 use std::thread;
 
-pub const THREADS: usize = 3;
-pub const MUTATE_THREAD_INDEX: usize = 2;
+pub const DEFAULT_READERS: usize = 2;
+pub const UI_THREAD: usize = 0;
+pub const COMPUTE_THREAD: usize = 1;
 
 thread_local! {
-    static CACHED_THREAD_INDEX: usize = match thread::current().name() {
-        Some(name) => match 1 + (name.as_bytes()[0] - '1' as u8) as usize {
-            i if i < THREADS => i,
-            _ => 0,
-        },
-        None => panic!("Invalid thread name to get index")
-    };
+    static BOUND_THREAD_INDEX: Cell<Option<usize>> = const { Cell::new(None) };
+}
+
+#[derive(Debug)]
+pub struct UnregisteredThread {
+    name: Option<String>,
+}
+
+impl fmt::Display for UnregisteredThread {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Thread {:?} is not bound to any slot, call SyncContext::register_thread first",
+            self.name
+        )
+    }
+}
+
+impl Error for UnregisteredThread {}
+
+// Dropping it on another thread would release the slot while the
+// registering thread still uses it
+///
+/// ```compile_fail,E0277
+/// fn assert_send<T: Send>(_: T) {}
+/// let ctx = tl_sync::SyncContext::new();
+/// assert_send(ctx.register_thread(tl_sync::UI_THREAD));
+/// ```
+pub struct ThreadGuard {
+    index: usize,
+    prev: Option<usize>,
+    ctx: SyncContext,
+    _ctx: ContextGuard,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl Drop for ThreadGuard {
+    fn drop(&mut self) {
+        let prev = self.prev;
+        BOUND_THREAD_INDEX.with(|c| c.set(prev));
+        self.ctx.unbind_slot(self.index);
+    }
+}
+
+impl SyncContext {
+    pub fn register_thread(&self, index: usize) -> ThreadGuard {
+        assert!(
+            index < self.readers(),
+            "Slot {} is not a reader slot, this context has {} reader(s)",
+            index,
+            self.readers()
+        );
+
+        // Each slot is only touched by one thread at a time
+        self.bind_slot(index);
+        let guard = self.enter();
+        let prev = BOUND_THREAD_INDEX.with(|c| c.replace(Some(index)));
+
+        ThreadGuard {
+            index,
+            prev,
+            ctx: self.clone(),
+            _ctx: guard,
+            _not_send: PhantomData,
+        }
+    }
+}
+
+pub fn current_thread_index() -> Option<usize> {
+    BOUND_THREAD_INDEX.with(|c| c.get())
+}
+
+pub fn try_thread_index() -> Result<usize, UnregisteredThread> {
+    match current_thread_index() {
+        Some(i) => Ok(i),
+        None => Err(UnregisteredThread {
+            name: thread::current().name().map(|it| it.into()),
+        }),
+    }
 }
 
 pub fn thread_index() -> usize {
-    CACHED_THREAD_INDEX.with(|c| *c)
+    match try_thread_index() {
+        Ok(i) => i,
+        Err(e) => panic!("{}", e),
+    }
 }
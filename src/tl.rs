@@ -1,10 +1,16 @@
 use super::*;
-use std::ops::Deref;
+use std::cell::RefCell;
+use std::error::Error;
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::rc::Rc;
 use std::sync::Arc;
 
 pub struct Tl<T> {
     // TODO Retry TrustRc (simple Rc inside) when possible
     cell: Arc<TrustCell<T>>,
+    // To restore the mutation slot when a transaction is rolled back
+    snapshot: Option<fn(&T) -> T>,
 }
 
 // impl<T> Drop for Tl<T> {
@@ -17,6 +23,7 @@ impl<T> Clone for Tl<T> {
     fn clone(&self) -> Self {
         Self {
             cell: self.cell.clone(),
+            snapshot: self.snapshot,
         }
     }
 }
@@ -25,80 +32,143 @@ impl<T> Deref for Tl<T> {
     type Target = T;
 
     fn deref(&self) -> &T {
+        self.cell.check_current_owner();
+        track_read(self.get_ptr());
         self.cell.get(thread_index())
     }
 }
 
-impl<T: 'static + ManualCopy<T>> Tl<T> {
-    pub fn to_mut(&self) -> &mut T {
-        // TODO Dev check if caller come from different places
-        // even in different sync calls, then should panic
+#[derive(Debug)]
+pub enum TryGetError {
+    Unregistered(UnregisteredThread),
+    OtherContext,
+}
 
-        {
-            let d = get_dirties().to_mut(thread_index());
-            let tmp = Box::new(self.clone());
-            let ptr = tmp.cell.arr.get();
-            let mut is_unique = true;
+impl From<UnregisteredThread> for TryGetError {
+    fn from(e: UnregisteredThread) -> Self {
+        TryGetError::Unregistered(e)
+    }
+}
 
-            for it in d.iter_mut() {
-                if it.1.get_ptr() == ptr as usize {
-                    if it.0 == 1 {
-                        panic!(
-                            "Only allow one mutation each sync {}, {}",
-                            it.0, ptr as usize
-                        );
-                    }
-                    if it.0 == 4 {
-                        panic!(
-                            "Only allow mutation from 1 thread {}, {}",
-                            it.0, ptr as usize
-                        );
-                    } else {
-                        it.0 = 1;
-                    }
+impl fmt::Display for TryGetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TryGetError::Unregistered(ref e) => write!(f, "{}", e),
+            TryGetError::OtherContext => write!(f, "Tl was created under another SyncContext"),
+        }
+    }
+}
 
-                    is_unique = false;
-                    break;
-                }
-            }
+impl Error for TryGetError {}
 
-            if is_unique {
-                d.push((1, tmp));
-            }
+// Read guard, Tl::write panics while one into the mutation slot is alive
+pub struct TlRef<'a, T: 'a> {
+    cell: &'a TrustCell<T>,
+    index: usize,
+    counted: bool,
+}
+
+impl<'a, T> Deref for TlRef<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.cell.get(self.index)
+    }
+}
+
+impl<'a, T> Drop for TlRef<'a, T> {
+    fn drop(&mut self) {
+        if self.counted {
+            self.cell.end_read();
         }
+    }
+}
+
+pub struct TlMut<'a, T: 'a> {
+    cell: &'a TrustCell<T>,
+    index: usize,
+}
+
+impl<'a, T> Deref for TlMut<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.cell.get(self.index)
+    }
+}
+
+impl<'a, T> DerefMut for TlMut<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // Only one TlMut can exist for a cell at a time,
+        // and nothing else reads the mutation slot outside of syncs
+        unsafe { self.cell.to_mut(self.index) }
+    }
+}
+
+impl<'a, T> Drop for TlMut<'a, T> {
+    fn drop(&mut self) {
+        self.cell.end_write();
+    }
+}
 
-        self.cell.to_mut(MUTATE_THREAD_INDEX)
-    }
-
-    pub fn to_mut_advanced(&self) -> &mut T {
-        {
-            let d = get_dirties().to_mut(thread_index());
-            let tmp = Box::new(self.clone());
-            let ptr = tmp.cell.arr.get();
-            let mut is_unique = true;
-
-            for it in d.iter_mut() {
-                if it.1.get_ptr() == ptr as usize {
-                    if it.0 == 4 {
-                        panic!(
-                            "Only allow mutation from 1 thread {}, {}",
-                            it.0, ptr as usize
-                        );
-                    } else {
-                        it.0 = 1;
+impl<T: 'static + Send + Sync + ManualCopy<T>> Tl<T> {
+    // Can be called many times before a sync, the mutation slot
+    // keeps the latest value. Panics on the second call in strict mode
+    pub fn write(&self) -> TlMut<'_, T> {
+        self.check_transaction();
+        self.write_inner(SyncContext::current().is_strict_writes())
+    }
+
+    // Never strict
+    pub fn write_advanced(&self) -> TlMut<'_, T> {
+        self.check_transaction();
+        self.write_inner(false)
+    }
+
+    fn check_transaction(&self) {
+        assert!(
+            self.snapshot.is_some() || !in_transaction(),
+            "Tl created by new_advanced cannot be rolled back, write it outside of transactions"
+        );
+    }
+
+    pub(crate) fn write_inner(&self, once_each_sync: bool) -> TlMut<'_, T> {
+        let ctx = SyncContext::current();
+        let ptr = self.get_ptr();
+        let index = ctx.mutate_index();
+
+        self.cell.check_owner(&ctx);
+        self.cell.begin_write();
+        let ret = TlMut {
+            cell: &self.cell,
+            index,
+        };
+
+        let buffered = buffer_write(ptr, once_each_sync, || {
+            let rollback = self.snapshot.map(|snapshot| {
+                let tl = self.clone();
+                let mut old = Some(snapshot(tl.cell.get(index)));
+
+                Box::new(move || {
+                    if let Some(old) = old.take() {
+                        tl.cell.begin_write();
+                        unsafe {
+                            *tl.cell.to_mut(index) = old;
+                        }
+                        tl.cell.end_write();
                     }
+                }) as Box<dyn FnMut()>
+            });
 
-                    is_unique = false;
-                    break;
-                }
-            }
+            (self.boxed(), rollback)
+        });
 
-            if is_unique {
-                d.push((1, tmp));
-            }
+        if !buffered {
+            let d = unsafe { ctx.dirties_mut(thread_index()) };
+            d.mark_mutated(ptr, once_each_sync, || self.boxed());
         }
 
-        self.cell.to_mut(MUTATE_THREAD_INDEX)
+        ret
     }
 }
 
@@ -108,7 +178,11 @@ impl<T> GetPtr for Tl<T> {
     }
 }
 
-impl<T: 'static + ManualCopy<T>> Dirty for Tl<T> {
+impl<T: 'static + Send + Sync + ManualCopy<T>> Dirty for Tl<T> {
+    fn check_context(&self, ctx: &SyncContext) {
+        self.cell.check_owner(ctx);
+    }
+
     fn sync(&self, from: usize, to: usize) {
         self.cell.inner_manual_copy(from, to);
     }
@@ -117,8 +191,25 @@ impl<T: 'static + ManualCopy<T>> Dirty for Tl<T> {
         self.cell.inner_manual_clear(to);
     }
 
+    fn forwarded(&self, from: usize) {
+        self.cell.inner_manual_forwarded(from);
+    }
+
     fn re_add(&self) {
-        self.to_mut();
+        self.write();
+    }
+
+    fn boxed(&self) -> Box<dyn Dirty> {
+        Box::new(self.clone())
+    }
+}
+
+// Tl is a handle, copying it shares the same cell
+impl<T> ManualCopy<Tl<T>> for Tl<T> {
+    fn copy_from(&mut self, other: &mut Tl<T>) {
+        if !Arc::ptr_eq(&self.cell, &other.cell) {
+            *self = other.clone();
+        }
     }
 }
 
@@ -130,22 +221,138 @@ impl<T: Default + Clone + ManualCopy<T>> Default for Tl<T> {
 
 impl<T: Clone> Tl<T> {
     pub fn new(value: T) -> Self {
-        // TODO Find a way that flexible with thread,
-        // but also not using std::mem::zeroed (error with Rc)
-        let tmp1 = value.clone();
-        let tmp2 = value.clone();
-        let a = [value, tmp1, tmp2];
+        let ctx = SyncContext::current();
+        let threads = ctx.threads();
+        let mut a = Vec::with_capacity(threads);
+        for _ in 1..threads {
+            a.push(value.clone());
+        }
+        a.push(value);
 
         Self {
-            cell: Arc::new(TrustCell::new(a)),
+            cell: Arc::new(TrustCell::owned(a, &ctx)),
+            snapshot: Some(T::clone),
         }
     }
 }
 
+impl<T: 'static + Clone> Tl<T> {
+    // Called with the value seen at the previous notify on this thread
+    // and the current one, not called on registration
+    pub fn on_change<F: 'static + FnMut(&T, &T)>(&self, f: F) -> ListenerHandleRef {
+        self.cell.check_current_owner();
+        let tl = self.clone();
+        let mut old = self.cell.get(thread_index()).clone();
+        let mut f = f;
+
+        listen_ptrs(
+            Phase::default(),
+            &[self.get_ptr()],
+            Rc::new(RefCell::new(move || {
+                tl.cell.check_current_owner();
+                let new = tl.cell.get(thread_index());
+                f(&old, new);
+                old.clone_from(new);
+            })),
+        )
+    }
+
+    // Snapshots of this Tl on this thread, taken at each of its notifies
+    pub fn stream(&self) -> Snapshots<T> {
+        self.stream_with(T::clone)
+    }
+}
+
+impl<T: 'static> Tl<T> {
+    // Like stream, with f picking the part to keep instead of cloning all
+    pub fn stream_with<U: 'static, F: 'static + FnMut(&T) -> U>(&self, f: F) -> Snapshots<U> {
+        self.cell.check_current_owner();
+        let tl = self.clone();
+        let mut f = f;
+
+        Snapshots::new(self.get_ptr(), move || {
+            tl.cell.check_current_owner();
+            f(tl.cell.get(thread_index()))
+        })
+    }
+}
+
 impl<T> Tl<T> {
-    pub fn new_advanced(a: [T; THREADS]) -> Self {
+    // Resolves at the next notify of this Tl on this thread
+    pub fn changed(&self) -> Changed {
+        Changed::new(self.get_ptr())
+    }
+
+    // The latest written value, before it is synced to this thread
+    pub fn pending(&self) -> TlRef<'_, T> {
+        let ctx = SyncContext::current();
+        self.cell.check_owner(&ctx);
+        track_read(self.get_ptr());
+        self.cell.begin_read();
+
+        TlRef {
+            cell: &self.cell,
+            index: ctx.mutate_index(),
+            counted: true,
+        }
+    }
+
+    // Same as deref, except for the pending value in read-own-writes mode
+    pub fn read(&self) -> TlRef<'_, T> {
+        self.cell.check_current_owner();
+        let ptr = self.get_ptr();
+        let index = thread_index();
+
+        if self.cell.is_read_own_writes() {
+            let ctx = SyncContext::current();
+            if is_buffered(ptr) || ctx.dirties().get(index).get(ptr) == Some(DirtyState::Mutated) {
+                return self.pending();
+            }
+        }
+
+        track_read(ptr);
+        TlRef {
+            cell: &self.cell,
+            index,
+            counted: false,
+        }
+    }
+
+    // When enabled, read on a thread with a pending write of this Tl
+    // returns the written value, shared by every clone of this Tl
+    pub fn set_read_own_writes(&self, enabled: bool) {
+        self.cell.set_read_own_writes(enabled);
+    }
+
+    pub fn is_read_own_writes(&self) -> bool {
+        self.cell.is_read_own_writes()
+    }
+
+    pub fn try_get(&self) -> Result<&T, TryGetError> {
+        let index = try_thread_index()?;
+        if !self.cell.is_current_owner() {
+            return Err(TryGetError::OtherContext);
+        }
+
+        track_read(self.get_ptr());
+        Ok(self.cell.get(index))
+    }
+
+    pub fn new_advanced(a: Vec<T>) -> Self {
         Self {
-            cell: Arc::new(TrustCell::new(a)),
+            cell: Arc::new(TrustCell::owned(a, &SyncContext::current())),
+            snapshot: None,
+        }
+    }
+
+    // Only for crate types which roll back by themselves, the write is
+    // not marked dirty
+    pub(crate) fn mutate_slot(&self) -> TlMut<'_, T> {
+        self.cell.begin_write();
+
+        TlMut {
+            cell: &self.cell,
+            index: SyncContext::current().mutate_index(),
         }
     }
 }
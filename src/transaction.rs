@@ -0,0 +1,130 @@
+use super::*;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::mem;
+
+// Writes of one transaction, dirty entries are only registered on commit
+#[derive(Default)]
+struct Frame {
+    dirties: Vec<(bool, Box<dyn Dirty>)>,
+    index: HashMap<usize, usize>,
+    rollbacks: Vec<Box<dyn FnMut()>>,
+}
+
+thread_local!(static FRAMES: RefCell<Vec<Frame>> = const { RefCell::new(vec![]) });
+
+struct FrameGuard {
+    done: bool,
+}
+
+impl Drop for FrameGuard {
+    fn drop(&mut self) {
+        // The closure panicked
+        if !self.done {
+            rollback();
+        }
+    }
+}
+
+// Writes inside f may repeat on the same Tl, they are published together
+// at the next sync when f returns Ok, and undone when it returns Err or panics.
+// Nested transactions are merged into the outer one on success
+pub fn transaction<R, E, F: FnOnce() -> Result<R, E>>(f: F) -> Result<R, E> {
+    FRAMES.with(|t| t.borrow_mut().push(Frame::default()));
+    let mut guard = FrameGuard { done: false };
+    let ret = f();
+    guard.done = true;
+
+    match ret {
+        Ok(_) => commit(),
+        Err(_) => rollback(),
+    }
+
+    ret
+}
+
+pub fn in_transaction() -> bool {
+    FRAMES.with(|t| !t.borrow().is_empty())
+}
+
+pub(crate) fn is_buffered(ptr: usize) -> bool {
+    FRAMES.with(|t| t.borrow().iter().any(|it| it.index.contains_key(&ptr)))
+}
+
+// Returns false when there is no transaction on this thread,
+// checkpoint is only taken on the first write of ptr in the transaction
+pub(crate) fn buffer_write<F>(ptr: usize, strict: bool, make: F) -> bool
+where
+    F: FnOnce() -> (Box<dyn Dirty>, Option<Box<dyn FnMut()>>),
+{
+    FRAMES.with(|t| {
+        let mut t = t.borrow_mut();
+        let frame = match t.last_mut() {
+            Some(frame) => frame,
+            None => return false,
+        };
+
+        match frame.index.get(&ptr) {
+            Some(&i) => frame.dirties[i].0 |= strict,
+            None => {
+                let (d, rollback) = make();
+                frame.index.insert(ptr, frame.dirties.len());
+                frame.dirties.push((strict, d));
+                frame.rollbacks.extend(rollback);
+            }
+        }
+
+        true
+    })
+}
+
+pub(crate) fn on_rollback(f: Box<dyn FnMut()>) {
+    FRAMES.with(|t| {
+        if let Some(frame) = t.borrow_mut().last_mut() {
+            frame.rollbacks.push(f);
+        }
+    });
+}
+
+fn commit() {
+    let frame = FRAMES.with(|t| t.borrow_mut().pop().unwrap());
+
+    let merged = FRAMES.with(|t| match t.borrow_mut().last_mut() {
+        Some(outer) => {
+            for (strict, d) in frame.dirties.into_iter() {
+                let ptr = d.get_ptr();
+                match outer.index.get(&ptr) {
+                    Some(&i) => outer.dirties[i].0 |= strict,
+                    None => {
+                        outer.index.insert(ptr, outer.dirties.len());
+                        outer.dirties.push((strict, d));
+                    }
+                }
+            }
+            outer.rollbacks.extend(frame.rollbacks);
+            None
+        }
+        None => Some(frame.dirties),
+    });
+
+    if let Some(dirties) = merged {
+        let ctx = SyncContext::current();
+        let d = unsafe { ctx.dirties_mut(thread_index()) };
+
+        for (strict, it) in dirties.into_iter() {
+            d.mark_mutated(it.get_ptr(), strict, move || it);
+        }
+    }
+}
+
+fn rollback() {
+    let mut rollbacks = FRAMES.with(|t| {
+        let mut frame = t.borrow_mut().pop().unwrap();
+        mem::take(&mut frame.rollbacks)
+    });
+
+    // Latest first, so every Tl ends at its value from before the transaction
+    for f in rollbacks.iter_mut().rev() {
+        f();
+    }
+}
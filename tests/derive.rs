@@ -0,0 +1,87 @@
+extern crate tl_sync;
+
+use tl_sync::*;
+
+#[derive(Clone, ManualCopy)]
+struct Model<T: Clone> {
+    count: usize,
+    name: String,
+    items: Vec<T>,
+    #[manual_copy(clone)]
+    tag: Option<T>,
+    #[manual_copy(skip)]
+    local: usize,
+}
+
+#[derive(Clone, ManualCopy)]
+enum Shape {
+    Empty,
+    Circle(u64),
+    Rect { w: u64, h: u64 },
+}
+
+// Clone is only required of the whole enum, for variant changes
+#[derive(Clone, ManualCopy)]
+enum Slot<T> {
+    Empty,
+    Full(T),
+}
+
+#[test]
+fn derive_struct() {
+    let mut a = Model {
+        count: 1,
+        name: "a".into(),
+        items: vec![1u8],
+        tag: None,
+        local: 7,
+    };
+    let mut b = Model {
+        count: 2,
+        name: "b".into(),
+        items: vec![2, 3],
+        tag: Some(4),
+        local: 8,
+    };
+
+    a.copy_from(&mut b);
+    assert!(a.count == 2);
+    assert!(a.name == "b");
+    assert!(a.items == vec![2, 3]);
+    assert!(a.tag == Some(4));
+    assert!(a.local == 7);
+}
+
+#[test]
+fn derive_enum() {
+    let mut a = Shape::Empty;
+
+    a.copy_from(&mut Shape::Rect { w: 1, h: 2 });
+    match a {
+        Shape::Rect { w: 1, h: 2 } => (),
+        _ => panic!("expected Rect"),
+    }
+
+    a.copy_from(&mut Shape::Circle(3));
+    match a {
+        Shape::Circle(3) => (),
+        _ => panic!("expected Circle"),
+    }
+}
+
+#[test]
+fn derive_generic_enum() {
+    let mut a: Slot<Vec<u8>> = Slot::Full(vec![1, 2]);
+
+    a.copy_from(&mut Slot::Full(vec![3]));
+    match a {
+        Slot::Full(ref v) if *v == vec![3] => (),
+        _ => panic!("expected Full"),
+    }
+
+    a.copy_from(&mut Slot::Empty);
+    match a {
+        Slot::Empty => (),
+        _ => panic!("expected Empty"),
+    }
+}
@@ -1,22 +1,38 @@
 extern crate tl_sync;
 
+use std::cell::{Cell, RefCell};
+use std::panic::{self, AssertUnwindSafe};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 use std::thread;
+use std::time::Duration;
 use tl_sync::*;
 
+// Publishes the writes of this thread, then notifies and clears it
+fn tick(ctx: &SyncContext) {
+    sync_from(ctx.mutate_index());
+    peek_notify(prepare_peek_notify());
+    sync_clear();
+}
+
 #[test]
 fn simple() {
-    init_dirties();
+    let ctx = SyncContext::new();
+    let _thread = ctx.register_thread(UI_THREAD);
 
     let a: Tl<usize> = Tl::new(1);
     assert!(*a == 1);
 
     let thread = {
         let a = a.clone();
+        let ctx = ctx.clone();
 
         thread::Builder::new()
             .name("1_test".into())
             .spawn(move || {
-                *a.to_mut() = 22;
+                let _thread = ctx.register_thread(COMPUTE_THREAD);
+                *a.write() = 22;
                 assert!(*a == 1);
 
                 thread::park();
@@ -34,3 +50,914 @@ fn simple() {
     thread.join().unwrap();
     assert!(*a == 22);
 }
+
+#[test]
+fn independent_contexts() {
+    let ctx1 = SyncContext::new();
+    let ctx2 = SyncContext::new();
+
+    let a: Tl<usize> = {
+        let _ctx = ctx1.register_thread(UI_THREAD);
+        let a = Tl::new(1);
+        *a.write() = 2;
+        a
+    };
+
+    assert!(ctx1.dirties().get(UI_THREAD).len() == 1);
+    assert!(ctx2.dirties().get(UI_THREAD).is_empty());
+
+    {
+        let _ctx = ctx2.register_thread(UI_THREAD);
+        sync_from(2);
+    }
+    {
+        // a is only usable under ctx1, and ctx2 did not sync it
+        let _ctx = ctx1.register_thread(UI_THREAD);
+        assert!(*a == 1);
+        sync_from(2);
+        assert!(*a == 2);
+    }
+}
+
+#[test]
+#[should_panic(expected = "created under another SyncContext")]
+fn tl_of_another_context() {
+    let ctx1 = SyncContext::with_readers(3);
+    let ctx2 = SyncContext::new();
+
+    let a: Tl<usize> = {
+        let _thread = ctx1.register_thread(UI_THREAD);
+        Tl::new(1)
+    };
+
+    let _thread = ctx2.register_thread(UI_THREAD);
+    *a.write() = 2;
+}
+
+#[test]
+fn try_get_of_another_context() {
+    let ctx1 = SyncContext::new();
+    let ctx2 = SyncContext::with_readers(3);
+
+    let a: Tl<usize> = {
+        let _thread = ctx1.register_thread(UI_THREAD);
+        Tl::new(1)
+    };
+
+    // Slot 2 does not exist in a
+    let _thread = ctx2.register_thread(2);
+    match a.try_get() {
+        Err(TryGetError::OtherContext) => (),
+        _ => panic!("try_get should fail under another context"),
+    }
+    assert!(panic::catch_unwind(AssertUnwindSafe(|| a.on_change(|_, _| ()))).is_err());
+    assert!(panic::catch_unwind(AssertUnwindSafe(|| a.stream())).is_err());
+}
+
+#[test]
+fn more_readers() {
+    let ctx = SyncContext::with_readers(3);
+    let _thread = ctx.register_thread(UI_THREAD);
+    assert!(ctx.mutate_index() == 3);
+
+    let a: Tl<usize> = Tl::new(1);
+    *a.write() = 5;
+    sync_from(ctx.mutate_index());
+    sync_to_many(&[1, 2]);
+
+    assert!(*a == 5);
+    assert!(ctx.dirties().get(1).len() == 1);
+    assert!(ctx.dirties().get(2).len() == 1);
+
+    for i in 1..3 {
+        let a = a.clone();
+        let ctx = ctx.clone();
+
+        thread::spawn(move || {
+            let _thread = ctx.register_thread(i);
+            assert!(*a == 5);
+        }).join()
+            .unwrap();
+    }
+}
+
+#[test]
+fn unregistered_thread() {
+    let ctx = SyncContext::new();
+    let a: Tl<usize> = {
+        let _thread = ctx.register_thread(UI_THREAD);
+        assert!(current_thread_index() == Some(UI_THREAD));
+        Tl::new(1)
+    };
+
+    assert!(current_thread_index().is_none());
+    assert!(a.try_get().is_err());
+}
+
+#[test]
+fn one_thread_per_slot() {
+    let ctx = SyncContext::new();
+    let _thread = ctx.register_thread(UI_THREAD);
+    // The same thread may bind again
+    drop(ctx.register_thread(UI_THREAD));
+
+    let taken = {
+        let ctx = ctx.clone();
+        thread::spawn(move || {
+            let _thread = ctx.register_thread(UI_THREAD);
+        }).join()
+    };
+    assert!(taken.is_err());
+
+    drop(_thread);
+    let ctx2 = ctx.clone();
+    thread::spawn(move || {
+        let _thread = ctx2.register_thread(UI_THREAD);
+    }).join()
+        .unwrap();
+}
+
+#[test]
+#[should_panic(expected = "already being written")]
+fn no_aliasing_writes() {
+    let ctx = SyncContext::new();
+    let _thread = ctx.register_thread(UI_THREAD);
+
+    let a: Tl<usize> = Tl::new(1);
+    let _w1 = a.write_advanced();
+    let _w2 = a.write_advanced();
+}
+
+#[test]
+fn dirty_state_transitions() {
+    let mut s = DirtyState::Mutated;
+    s.advance(DirtyState::Synced, 1);
+    s.advance(DirtyState::Notified, 1);
+    s.advance(DirtyState::Mutated, 1);
+    assert!(s == DirtyState::Mutated);
+
+    let e = DirtyState::Received.transition(DirtyState::Mutated, 1).unwrap_err();
+    assert!(format!("{}", e).contains("only allow mutation from 1 thread"));
+    assert!(DirtyState::Notified.transition(DirtyState::Consumed, 1).is_err());
+}
+
+#[test]
+fn sync_more_types() {
+    let ctx = SyncContext::new();
+    let _thread = ctx.register_thread(UI_THREAD);
+
+    let a: Tl<f32> = Tl::new(1.0);
+    let b: Tl<[u8; 64]> = Tl::new([0; 64]);
+    let c: Tl<(bool, char, i32)> = Tl::new((false, 'a', 0));
+    let d: Tl<[u8; 100]> = Tl::new([0; 100]);
+
+    *a.write() = 2.5;
+    b.write()[63] = 9;
+    *c.write() = (true, 'b', -1);
+    d.write()[99] = 7;
+    sync_from(ctx.mutate_index());
+
+    assert!(*a == 2.5);
+    assert!(b[63] == 9);
+    assert!(*c == (true, 'b', -1));
+    assert!(d[99] == 7);
+}
+
+#[test]
+fn sync_tracked_collections() {
+    let ctx = SyncContext::with_readers(3);
+    let _thread = ctx.register_thread(UI_THREAD);
+
+    let v: Tl<TlVec<usize>> = Tl::new((0..1000).collect::<Vec<_>>().into());
+    let m: Tl<TlMap<usize, usize>> = Tl::new(TlMap::new());
+
+    {
+        let mut v = v.write();
+        v.set(10, 100);
+        *v.get_mut(20).unwrap() = 200;
+        v.push(1000);
+    }
+    {
+        let mut m = m.write();
+        m.insert(1, 1);
+        m.insert(2, 2);
+        m.remove(&1);
+    }
+    sync_from(ctx.mutate_index());
+    sync_to_many(&[1, 2]);
+
+    assert!(v[10] == 100 && v[20] == 200 && v.len() == 1001);
+    assert!(m.get(&1).is_none() && m[&2] == 2);
+
+    for i in 1..3 {
+        let v = v.clone();
+        let m = m.clone();
+        let ctx = ctx.clone();
+
+        thread::spawn(move || {
+            let _thread = ctx.register_thread(i);
+            assert!(v[10] == 100 && v[20] == 200 && v[1000] == 1000);
+            assert!(m.len() == 1 && m[&2] == 2);
+        }).join()
+            .unwrap();
+    }
+}
+
+// Counts clones on this thread, to tell replayed edits from full copies
+thread_local!(static CLONES: Cell<usize> = const { Cell::new(0) });
+
+#[derive(Debug, PartialEq)]
+struct Counted(usize);
+
+impl Clone for Counted {
+    fn clone(&self) -> Self {
+        CLONES.with(|c| c.set(c.get() + 1));
+        Counted(self.0)
+    }
+}
+
+fn take_clones() -> usize {
+    CLONES.with(|c| c.replace(0))
+}
+
+#[test]
+fn tracked_collection_edits() {
+    let mut a: TlVec<Counted> = (0..10).map(Counted).collect::<Vec<_>>().into();
+    let mut b = a.clone();
+    let mut c = a.clone();
+    take_clones();
+
+    // Shrinking is not logged, the replay still truncates
+    a.pop();
+    a.truncate(8);
+    b.copy_from(&mut a);
+    assert!(b[..] == a[..] && take_clones() == 0);
+
+    // c missed two syncs and still gets every edit since its version
+    a.set(1, Counted(100));
+    b.copy_from(&mut a);
+    a.set(2, Counted(200));
+    b.copy_from(&mut a);
+    c.copy_from(&mut a);
+    assert!(b[..] == a[..] && c[..] == a[..]);
+    assert!(take_clones() == 4);
+
+    a.clear();
+    a.push(Counted(7));
+    a.push(Counted(8));
+    b.copy_from(&mut a);
+    assert!(b[..] == a[..] && take_clones() == 2);
+
+    // Shifting edits reset the log, every item is copied
+    a.insert(0, Counted(9));
+    b.copy_from(&mut a);
+    assert!(b[..] == a[..] && take_clones() == 3);
+
+    a.remove(1);
+    b.copy_from(&mut a);
+    assert!(b[..] == a[..] && take_clones() == 2);
+
+    a.as_mut_slice()[0] = Counted(10);
+    b.copy_from(&mut a);
+    assert!(b[..] == a[..] && take_clones() == 2);
+
+    // More edits than the log keeps, b is behind its start
+    for i in 0..100 {
+        a.set(1, Counted(i));
+    }
+    take_clones();
+    b.copy_from(&mut a);
+    assert!(b[..] == a[..] && take_clones() == 2);
+
+    let mut m: TlMap<usize, Counted> = TlMap::new();
+    m.insert(1, Counted(1));
+    m.insert(2, Counted(2));
+    let mut n = m.clone();
+    let mut o = m.clone();
+
+    m.remove(&1);
+    n.copy_from(&mut m);
+    m.insert(3, Counted(3));
+    n.copy_from(&mut m);
+    o.copy_from(&mut m);
+    assert!(*n == *m && *o == *m);
+
+    // Replaying the insert alone would keep 2 and 3
+    m.clear();
+    m.insert(4, Counted(4));
+    n.copy_from(&mut m);
+    assert!(*n == *m);
+
+    for i in 0..100 {
+        m.get_mut(&4).unwrap().0 = i;
+    }
+    n.copy_from(&mut m);
+    o.copy_from(&mut m);
+    assert!(*n == *m && *o == *m);
+}
+
+#[test]
+fn parallel_sync() {
+    let ctx = SyncContext::new();
+    let _thread = ctx.register_thread(UI_THREAD);
+    ctx.set_parallel_sync(true);
+    ctx.set_parallel_copy_threshold(1024);
+
+    let a: Vec<Tl<Vec<usize>>> = (0..8).map(|_| Tl::new(vec![0; 10_000])).collect();
+    for (i, it) in a.iter().enumerate() {
+        for v in it.write().iter_mut() {
+            *v = i;
+        }
+    }
+    sync_from(ctx.mutate_index());
+
+    for (i, it) in a.iter().enumerate() {
+        assert!(it.iter().all(|v| *v == i));
+    }
+
+    // Every Vec goes to the pool, even those shorter than its thread count
+    ctx.set_parallel_copy_threshold(0);
+    let b: Tl<Vec<usize>> = Tl::new(vec![]);
+    let c: Tl<Vec<usize>> = Tl::new(vec![0]);
+    b.write().push(1);
+    c.write().clear();
+    sync_from(ctx.mutate_index());
+
+    assert!(*b == vec![1] && c.is_empty());
+    // Not seen by tests running other contexts meanwhile
+    assert!(SyncContext::new().parallel_copy_threshold() == usize::MAX);
+}
+
+#[test]
+fn listen_many_sources() {
+    let ctx = SyncContext::new();
+    let _thread = ctx.register_thread(UI_THREAD);
+
+    let a: Tl<usize> = Tl::new(0);
+    let b: Tl<usize> = Tl::new(0);
+    let c: Action<usize> = Action::new();
+    let calls = Rc::new(Cell::new(0));
+
+    let _h = register_listener(&[&a, &b, &c], {
+        let calls = calls.clone();
+        move || calls.set(calls.get() + 1)
+    });
+    assert!(calls.get() == 1);
+
+    *a.write() = 1;
+    *b.write() = 2;
+    c.fire(3);
+    tick(&ctx);
+
+    assert!(calls.get() == 2);
+}
+
+#[test]
+fn computed() {
+    let ctx = SyncContext::new();
+    let _thread = ctx.register_thread(UI_THREAD);
+
+    let use_a: Tl<bool> = Tl::new(true);
+    let a: Tl<usize> = Tl::new(1);
+    let b: Tl<usize> = Tl::new(10);
+    let c = Computed::new({
+        let (use_a, a, b) = (use_a.clone(), a.clone(), b.clone());
+        move || if *use_a { *a * 2 } else { *b * 2 }
+    });
+    let calls = Rc::new(Cell::new(0));
+    let _h = register_listener(&[&c], {
+        let calls = calls.clone();
+        move || calls.set(calls.get() + 1)
+    });
+    assert!(*c == 2);
+
+    *a.write() = 2;
+    tick(&ctx);
+    tick(&ctx);
+    assert!(*c == 4);
+    assert!(calls.get() == 2);
+
+    // b is not a dependency yet
+    *b.write() = 20;
+    tick(&ctx);
+    tick(&ctx);
+    assert!(calls.get() == 2);
+
+    *use_a.write() = false;
+    tick(&ctx);
+    tick(&ctx);
+    assert!(*c == 40);
+    assert!(calls.get() == 3);
+
+    // a is not a dependency anymore
+    *a.write() = 3;
+    tick(&ctx);
+    tick(&ctx);
+    assert!(calls.get() == 3);
+}
+
+#[test]
+fn on_change() {
+    let ctx = SyncContext::new();
+    let _thread = ctx.register_thread(UI_THREAD);
+
+    let a: Tl<String> = Tl::new("a".to_owned());
+    let changes = Rc::new(RefCell::new(vec![]));
+    let _h = a.on_change({
+        let changes = changes.clone();
+        move |old, new| changes.borrow_mut().push(format!("{} -> {}", old, new))
+    });
+    assert!(changes.borrow().is_empty());
+
+    for s in &["b", "c"] {
+        *a.write() = s.to_string();
+        tick(&ctx);
+    }
+
+    assert!(*changes.borrow() == vec!["a -> b", "b -> c"]);
+}
+
+#[test]
+fn drop_listener_on_other_thread() {
+    let ctx = SyncContext::new();
+    let _thread = ctx.register_thread(UI_THREAD);
+
+    let a: Tl<usize> = Tl::new(0);
+    let calls = Arc::new(AtomicUsize::new(0));
+    let h = register_listener(&[&a], {
+        let calls = calls.clone();
+        move || {
+            calls.fetch_add(1, Ordering::SeqCst);
+        }
+    });
+
+    thread::spawn(move || drop(h)).join().unwrap();
+    assert!(ctx.listeners().get(UI_THREAD).len() == 1);
+
+    *a.write() = 1;
+    tick(&ctx);
+
+    assert!(calls.load(Ordering::SeqCst) == 1);
+    assert!(ctx.listeners().get(UI_THREAD).is_empty());
+}
+
+#[test]
+fn subscriptions() {
+    let ctx = SyncContext::new();
+    let _thread = ctx.register_thread(UI_THREAD);
+
+    let a: Tl<usize> = Tl::new(0);
+    let subs = Subscriptions::new();
+    let child = subs.child();
+    let weak = subs.clone_weak();
+
+    subs.listen(&[&a], || {});
+    child.listen(&[&a], || {});
+    weak.listen(&[&a], || {});
+    assert!(subs.len() == 3);
+    assert!(ctx.listeners().get(UI_THREAD).get(&a.get_ptr()).unwrap().len() == 3);
+
+    subs.clear();
+    assert!(child.is_empty());
+    assert!(ctx.listeners().get(UI_THREAD).is_empty());
+
+    let child = subs.child();
+    subs.listen(&[&a], || {});
+    child.listen(&[&a], || {});
+    drop(subs);
+    assert!(!weak.is_alive());
+    assert!(child.is_alive() && child.is_empty());
+    weak.listen(&[&a], || {});
+    assert!(ctx.listeners().get(UI_THREAD).is_empty());
+}
+
+#[test]
+fn listener_phases() {
+    let ctx = SyncContext::new();
+    let _thread = ctx.register_thread(UI_THREAD);
+
+    let a: Tl<usize> = Tl::new(0);
+    let b: Tl<usize> = Tl::new(0);
+    let calls = Rc::new(RefCell::new(vec![]));
+    let subs = Subscriptions::new();
+
+    for &(phase, name) in &[
+        (Phase::Render, "render"),
+        (Phase::Compute, "compute 1"),
+        (Phase::Validate, "validate"),
+        (Phase::Compute, "compute 2"),
+    ] {
+        let calls = calls.clone();
+        subs.listen_in(phase, &[&a, &b], move || calls.borrow_mut().push(name));
+    }
+    calls.borrow_mut().clear();
+
+    *b.write() = 1;
+    *a.write() = 1;
+    tick(&ctx);
+
+    assert!(*calls.borrow() == vec!["validate", "compute 1", "compute 2", "render"]);
+}
+
+#[test]
+fn notify_until_stable_cascades() {
+    let ctx = SyncContext::new();
+    let _thread = ctx.register_thread(UI_THREAD);
+
+    let a: Tl<usize> = Tl::new(0);
+    let b: Tl<usize> = Tl::new(0);
+    let c: Tl<usize> = Tl::new(0);
+    let subs = Subscriptions::new();
+
+    subs.listen(&[&a], {
+        let (a, b) = (a.clone(), b.clone());
+        move || if *a > 0 { *b.write() = *a + 1 }
+    });
+    subs.listen(&[&b], {
+        let (b, c) = (b.clone(), c.clone());
+        move || if *b > 0 { *c.write() = *b + 1 }
+    });
+
+    *a.write() = 1;
+    assert!(notify_until_stable(ctx.mutate_index(), 10).unwrap() == 3);
+    assert!(*c == 3);
+
+    // c -> a closes the loop
+    subs.listen(&[&c], {
+        let (a, c) = (a.clone(), c.clone());
+        move || if *c > 3 { *a.write() = *c + 1 }
+    });
+    *a.write() = 2;
+
+    let e = notify_until_stable(ctx.mutate_index(), 10).unwrap_err();
+    assert!(e.ptrs.len() == 1);
+    assert!(e.ptrs[0].1 > 1);
+    assert!(format!("{}", e).contains("after 10 notify rounds"));
+
+    let reported = Arc::new(AtomicUsize::new(0));
+    ctx.set_on_notify_cycle({
+        let reported = reported.clone();
+        move |e| reported.store(e.depth, Ordering::Relaxed)
+    });
+    ctx.report_notify_cycle(&e);
+    assert!(reported.load(Ordering::Relaxed) == 10);
+
+    // Out of budget before the first round, left dirty for the next call
+    let budget = Duration::from_millis(0);
+    assert!(notify_until_stable_within(ctx.mutate_index(), 10, budget).unwrap() == 0);
+    assert!(ctx.dirties().get(UI_THREAD).get(e.ptrs[0].0) == Some(DirtyState::Mutated));
+}
+
+#[test]
+fn transactions() {
+    let ctx = SyncContext::new();
+    let _thread = ctx.register_thread(UI_THREAD);
+
+    let a: Tl<usize> = Tl::new(0);
+    let b: Tl<String> = Tl::new("".to_owned());
+    let on_a: Action<usize> = Action::new();
+
+    // Several writes of the same Tl are fine inside one transaction
+    let r: Result<(), ()> = transaction(|| {
+        *a.write() = 1;
+        *a.write() += 1;
+        b.write().push('x');
+        Ok(())
+    });
+    assert!(r.is_ok());
+    assert!(*a == 0);
+    tick(&ctx);
+    assert!(*a == 2 && *b == "x");
+
+    let r: Result<(), &str> = transaction(|| {
+        *a.write() = 10;
+        b.write().push('y');
+        on_a.fire(10);
+        Err("nope")
+    });
+    assert!(r == Err("nope"));
+    assert!(ctx
+        .dirties()
+        .get(UI_THREAD)
+        .iter()
+        .all(|it| it.0 != DirtyState::Mutated));
+    tick(&ctx);
+    assert!(*a == 2 && *b == "x" && on_a.is_empty());
+
+    let r = panic::catch_unwind(AssertUnwindSafe(|| {
+        let _: Result<(), ()> = transaction(|| {
+            *a.write() = 20;
+            let _: Result<(), ()> = transaction(|| {
+                *a.write() = 30;
+                Ok(())
+            });
+            panic!("oops");
+        });
+    }));
+    assert!(r.is_err());
+    assert!(!in_transaction());
+
+    *a.write() += 1;
+    tick(&ctx);
+    assert!(*a == 3);
+}
+
+#[test]
+fn many_writes_each_sync() {
+    let ctx = SyncContext::new();
+    let _thread = ctx.register_thread(UI_THREAD);
+
+    let a: Tl<usize> = Tl::new(0);
+    *a.write() += 1;
+    *a.write() += 1;
+    sync_from(ctx.mutate_index());
+    assert!(*a == 2);
+}
+
+#[test]
+#[should_panic(expected = "strict writes only allow one")]
+fn strict_writes() {
+    let ctx = SyncContext::new();
+    let _thread = ctx.register_thread(UI_THREAD);
+    ctx.set_strict_writes(true);
+
+    let a: Tl<usize> = Tl::new(0);
+    *a.write() += 1;
+    *a.write() += 1;
+}
+
+#[test]
+fn read_own_writes() {
+    let ctx = SyncContext::new();
+    let _thread = ctx.register_thread(UI_THREAD);
+
+    let a: Tl<usize> = Tl::new(1);
+    let b: Tl<usize> = Tl::new(1);
+    b.set_read_own_writes(true);
+
+    *a.write() = 2;
+    *b.write() = 2;
+    assert!(*a == 1 && *a.pending() == 2);
+    assert!(*b == 1 && *b.read() == 2);
+
+    let _: Result<(), ()> = transaction(|| {
+        *b.write() = 3;
+        assert!(*b.read() == 3);
+        Ok(())
+    });
+
+    sync_from(ctx.mutate_index());
+    assert!(*a == 2 && *b == 3);
+}
+
+#[test]
+#[should_panic(expected = "pending value is being read")]
+fn no_write_while_reading_pending() {
+    let ctx = SyncContext::new();
+    let _thread = ctx.register_thread(UI_THREAD);
+
+    let a: Tl<usize> = Tl::new(1);
+    let pending = a.pending();
+    *a.write() = 2;
+    assert!(*pending == 1);
+}
+
+#[test]
+fn action_subscribe() {
+    let ctx = SyncContext::new();
+    let _thread = ctx.register_thread(UI_THREAD);
+
+    let a: Tl<usize> = Tl::new(0);
+    let on_add: Action<usize> = Action::new();
+    let seen = Rc::new(RefCell::new(vec![]));
+    let subs = Subscriptions::new();
+
+    subs.add(on_add.subscribe({
+        let seen = seen.clone();
+        move |events| seen.borrow_mut().push(events.to_vec())
+    }));
+    subs.add(on_add.subscribe({
+        let on_add = on_add.clone();
+        move |events| {
+            assert!(on_add.drain().len() == 2);
+            assert!(events.len() == 2 && on_add.len() == 2 && on_add.peek().is_empty());
+        }
+    }));
+    subs.add(on_add.subscribe(|_| panic!("drained before")));
+
+    *a.write() = 1;
+    tick(&ctx);
+    assert!(seen.borrow().is_empty());
+
+    on_add.fire(1);
+    on_add.fire(2);
+    tick(&ctx);
+    assert!(*seen.borrow() == vec![vec![1, 2]]);
+    assert!(on_add.peek().is_empty());
+}
+
+#[test]
+fn action_fan_out() {
+    let ctx = SyncContext::with_readers(3);
+    let on_add: Action<usize> = {
+        let _thread = ctx.register_thread(UI_THREAD);
+        Action::new()
+    };
+    let seen = Rc::new(RefCell::new(vec![]));
+    let handles: Vec<_> = (0..3)
+        .map(|i| {
+            let _thread = ctx.register_thread(i);
+            let seen = seen.clone();
+            on_add.subscribe(move |events| seen.borrow_mut().push((i, events.to_vec())))
+        }).collect();
+
+    {
+        let _thread = ctx.register_thread(UI_THREAD);
+        on_add.fire(1);
+        on_add.fire(2);
+        sync_from(ctx.mutate_index());
+        let prepared = prepare_peek_notify();
+        sync_to_many(&[1, 2]);
+        peek_notify(prepared);
+        sync_clear();
+        assert!(on_add.is_empty());
+    }
+
+    for i in 1..3 {
+        let _thread = ctx.register_thread(i);
+        peek_notify(prepare_peek_notify());
+        sync_clear();
+        assert!(on_add.is_empty());
+    }
+
+    assert!(*seen.borrow() == vec![(0, vec![1, 2]), (1, vec![1, 2]), (2, vec![1, 2])]);
+    for (i, h) in handles.into_iter().enumerate() {
+        let _thread = ctx.register_thread(i);
+        drop(h);
+    }
+}
+
+#[test]
+fn action_capacity() {
+    let ctx = SyncContext::new();
+    let _thread = ctx.register_thread(UI_THREAD);
+
+    let oldest: Action<usize> = Action::with_capacity(2, Overflow::DropOldest);
+    let newest: Action<usize> = Action::with_capacity(2, Overflow::DropNewest);
+    for i in 1..4 {
+        oldest.fire(i);
+        newest.fire(i);
+    }
+    sync_from(ctx.mutate_index());
+
+    assert!(oldest.to_vec() == vec![2, 3]);
+    assert!(newest.to_vec() == vec![1, 2]);
+    peek_notify(prepare_peek_notify());
+    sync_clear();
+
+    // Rolled back fires restore the events they dropped
+    oldest.fire(1);
+    oldest.fire(2);
+    let _: Result<(), ()> = transaction(|| {
+        oldest.fire(3);
+        oldest.fire(4);
+        Err(())
+    });
+    sync_from(ctx.mutate_index());
+    assert!(oldest.to_vec() == vec![1, 2]);
+}
+
+#[test]
+fn request_replies() {
+    let ctx = SyncContext::new();
+    let _thread = ctx.register_thread(UI_THREAD);
+
+    let on_buy: Request<usize, usize> = Request::new();
+    let seen = Rc::new(RefCell::new(vec![]));
+    let subs = Subscriptions::new();
+
+    subs.add(on_buy.handle(|price| {
+        if *price <= 10 {
+            Ok(10 - *price)
+        } else {
+            Err("too expensive".into())
+        }
+    }));
+    subs.add(on_buy.on_reply({
+        let seen = seen.clone();
+        move |ticket, result| seen.borrow_mut().push((ticket, result.clone()))
+    }));
+
+    let cheap = on_buy.send(3);
+    let expensive = on_buy.send(30);
+    assert!(cheap != expensive);
+
+    tick(&ctx);
+    assert!(seen.borrow().is_empty());
+
+    tick(&ctx);
+    assert!(
+        *seen.borrow() == vec![(cheap, Ok(7)), (expensive, Err("too expensive".to_string()))]
+    );
+}
+
+#[test]
+fn tasks() {
+    let ctx = SyncContext::new();
+    let _thread = ctx.register_thread(UI_THREAD);
+    let tick_tasks = || {
+        tick(&ctx);
+        run_tasks()
+    };
+
+    let a: Tl<usize> = Tl::new(0);
+    let on_add: Action<usize> = Action::new();
+    let seen = Rc::new(RefCell::new(vec![]));
+
+    spawn_local({
+        let a = a.clone();
+        let seen = seen.clone();
+        // Created before awaiting, so events fired with the change are kept
+        let next = on_add.next();
+
+        async move {
+            a.changed().await;
+            seen.borrow_mut().push(*a);
+
+            let events = next.await;
+            seen.borrow_mut().extend(events.iter());
+
+            next_sync().await;
+        }
+    });
+
+    assert!(tick_tasks() == 1);
+    assert!(seen.borrow().is_empty());
+
+    *a.write() = 5;
+    on_add.fire(1);
+    assert!(tick_tasks() == 1);
+    assert!(*seen.borrow() == vec![5, 1]);
+
+    assert!(tick_tasks() == 0);
+    assert!(ctx.listeners().get(UI_THREAD).is_empty());
+}
+
+#[test]
+fn streams() {
+    let ctx = SyncContext::new();
+    let _thread = ctx.register_thread(UI_THREAD);
+    // Same steps as the runner UI tick, which syncs more than once
+    let ui_tick = || {
+        tick(&ctx);
+        notify_until_stable(ctx.mutate_index(), ctx.max_notify_depth()).unwrap();
+        run_tasks();
+        advance_tick();
+    };
+
+    let a: Tl<usize> = Tl::new(0);
+    let b: Tl<usize> = Tl::new(0);
+    let on_add: Action<usize> = Action::new();
+    let distinct = Rc::new(RefCell::new(vec![]));
+    let debounced = Rc::new(RefCell::new(vec![]));
+    let throttled = Rc::new(RefCell::new(vec![]));
+
+    spawn_local(a.stream().distinct().for_each({
+        let distinct = distinct.clone();
+        move |it| distinct.borrow_mut().push(it)
+    }));
+    spawn_local(b.stream().debounce(2).for_each({
+        let debounced = debounced.clone();
+        move |it| debounced.borrow_mut().push(it)
+    }));
+    spawn_local(on_add.stream().throttle(2).for_each({
+        let throttled = throttled.clone();
+        move |it| throttled.borrow_mut().push(it)
+    }));
+    ui_tick();
+
+    *a.write() = 1;
+    *b.write() = 1;
+    on_add.fire(1);
+    on_add.fire(2);
+    ui_tick();
+
+    *a.write() = 1;
+    *b.write() = 2;
+    on_add.fire(3);
+    ui_tick();
+    assert!(debounced.borrow().is_empty());
+
+    *a.write() = 2;
+    on_add.fire(4);
+    ui_tick();
+    assert!(debounced.borrow().is_empty());
+    ui_tick();
+
+    assert!(*distinct.borrow() == vec![1, 2]);
+    assert!(*debounced.borrow() == vec![2]);
+    assert!(*throttled.borrow() == vec![1, 4]);
+
+    clear_tasks();
+    assert!(ctx.listeners().get(UI_THREAD).is_empty());
+}
@@ -0,0 +1,232 @@
+extern crate proc_macro;
+extern crate proc_macro2;
+#[macro_use]
+extern crate quote;
+#[macro_use]
+extern crate syn;
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use syn::{Data, DeriveInput, Field, Fields, Ident, Index, Meta, NestedMeta};
+
+#[derive(Clone, Copy, PartialEq)]
+enum Mode {
+    Copy,
+    Clone,
+    Skip,
+}
+
+// Enums copy field by field while both sides are the same variant, otherwise
+// the whole value is cloned. So they get a Clone bound, and their fields
+// cannot be skipped since a clone would overwrite them anyway
+#[proc_macro_derive(ManualCopy, attributes(manual_copy))]
+pub fn derive_manual_copy(input: TokenStream) -> TokenStream {
+    let input: DeriveInput = syn::parse(input).unwrap();
+    let name = &input.ident;
+
+    let (copy_body, clear_body, fields) = match input.data {
+        Data::Struct(ref data) => derive_struct(&data.fields),
+        Data::Enum(ref data) => {
+            let mut copy_arms = vec![];
+            let mut clear_arms = vec![];
+            let mut fields = vec![];
+
+            for v in data.variants.iter() {
+                if v.fields.iter().any(|f| field_mode(f) == Mode::Skip) {
+                    panic!(
+                        "#[manual_copy(skip)] is not supported on enum fields, \
+                         changing variant clones the whole value"
+                    );
+                }
+
+                let (copy_arm, clear_arm) = derive_variant(name, &v.ident, &v.fields);
+                copy_arms.push(copy_arm);
+                clear_arms.push(clear_arm);
+                fields.extend(v.fields.iter().cloned());
+            }
+
+            (
+                quote! {
+                    match (&mut *self, &mut *other) {
+                        #(#copy_arms)*
+                        _ => (),
+                    }
+                    // Different variants, nothing to reuse
+                    *self = ::std::clone::Clone::clone(other);
+                },
+                quote! {
+                    match *self {
+                        #(#clear_arms)*
+                    }
+                },
+                fields,
+            )
+        }
+        Data::Union(_) => panic!("ManualCopy cannot be derived for unions"),
+    };
+
+    let mut generics = input.generics.clone();
+    if let Data::Enum(_) = input.data {
+        let (_, ty_generics, _) = input.generics.split_for_impl();
+        generics
+            .make_where_clause()
+            .predicates
+            .push(parse_quote!(#name #ty_generics: ::std::clone::Clone));
+    }
+    if generics.type_params().next().is_some() {
+        let where_clause = generics.make_where_clause();
+
+        for f in fields.iter() {
+            let ty = &f.ty;
+
+            match field_mode(f) {
+                Mode::Copy => where_clause
+                    .predicates
+                    .push(parse_quote!(#ty: ::tl_sync::ManualCopy<#ty>)),
+                Mode::Clone => where_clause
+                    .predicates
+                    .push(parse_quote!(#ty: ::std::clone::Clone)),
+                Mode::Skip => (),
+            }
+        }
+    }
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+    let expanded = quote! {
+        impl #impl_generics ::tl_sync::ManualCopy<#name #ty_generics> for #name #ty_generics
+            #where_clause
+        {
+            #[allow(unused_variables, unreachable_code, unreachable_patterns)]
+            fn copy_from(&mut self, other: &mut #name #ty_generics) {
+                #copy_body
+            }
+
+            #[allow(unused_variables)]
+            fn clear(&mut self) {
+                #clear_body
+            }
+        }
+    };
+
+    expanded.into()
+}
+
+fn field_mode(f: &Field) -> Mode {
+    let mut mode = Mode::Copy;
+
+    for attr in f.attrs.iter() {
+        let list = match attr.interpret_meta() {
+            Some(Meta::List(ref list)) if list.ident == "manual_copy" => list.clone(),
+            _ => continue,
+        };
+
+        for it in list.nested.iter() {
+            mode = match *it {
+                NestedMeta::Meta(Meta::Word(ref w)) if w == "skip" => Mode::Skip,
+                NestedMeta::Meta(Meta::Word(ref w)) if w == "clone" => Mode::Clone,
+                _ => panic!("Expected #[manual_copy(skip)] or #[manual_copy(clone)]"),
+            };
+        }
+    }
+
+    mode
+}
+
+fn copy_field(mode: Mode, s: &TokenStream2, o: &TokenStream2) -> TokenStream2 {
+    match mode {
+        Mode::Copy => quote! { ::tl_sync::ManualCopy::copy_from(#s, #o); },
+        Mode::Clone => quote! { *#s = ::std::clone::Clone::clone(&*#o); },
+        Mode::Skip => quote!{},
+    }
+}
+
+fn clear_field(mode: Mode, s: &TokenStream2) -> TokenStream2 {
+    match mode {
+        Mode::Copy => quote! { ::tl_sync::ManualCopy::clear(#s); },
+        Mode::Clone | Mode::Skip => quote!{},
+    }
+}
+
+fn derive_struct(fields: &Fields) -> (TokenStream2, TokenStream2, Vec<Field>) {
+    let mut copies = vec![];
+    let mut clears = vec![];
+
+    for (i, f) in fields.iter().enumerate() {
+        let mode = field_mode(f);
+        let member = match f.ident {
+            Some(ref ident) => quote! { #ident },
+            None => {
+                let index = Index::from(i);
+                quote! { #index }
+            }
+        };
+
+        copies.push(copy_field(
+            mode,
+            &quote! { &mut self.#member },
+            &quote! { &mut other.#member },
+        ));
+        clears.push(clear_field(mode, &quote! { &mut self.#member }));
+    }
+
+    (
+        quote! { #(#copies)* },
+        quote! { #(#clears)* },
+        fields.iter().cloned().collect(),
+    )
+}
+
+fn derive_variant(name: &Ident, variant: &Ident, fields: &Fields) -> (TokenStream2, TokenStream2) {
+    let mut self_binds = vec![];
+    let mut other_binds = vec![];
+    let mut copies = vec![];
+    let mut clears = vec![];
+
+    for (i, f) in fields.iter().enumerate() {
+        let mode = field_mode(f);
+        let s = Ident::new(&format!("__self_{}", i), Span::call_site());
+        let o = Ident::new(&format!("__other_{}", i), Span::call_site());
+
+        if mode == Mode::Skip {
+            self_binds.push(quote! { _ });
+            other_binds.push(quote! { _ });
+        } else {
+            self_binds.push(quote! { ref mut #s });
+            other_binds.push(quote! { ref mut #o });
+        }
+
+        copies.push(copy_field(mode, &quote! { #s }, &quote! { #o }));
+        clears.push(clear_field(mode, &quote! { #s }));
+    }
+
+    let (self_pat, other_pat) = match *fields {
+        Fields::Named(_) => {
+            let idents: Vec<_> = fields.iter().map(|f| f.ident.clone().unwrap()).collect();
+            let idents2 = idents.clone();
+
+            (
+                quote! { #name::#variant { #(#idents: #self_binds),* } },
+                quote! { #name::#variant { #(#idents2: #other_binds),* } },
+            )
+        }
+        Fields::Unnamed(_) => (
+            quote! { #name::#variant(#(#self_binds),*) },
+            quote! { #name::#variant(#(#other_binds),*) },
+        ),
+        Fields::Unit => (quote! { #name::#variant }, quote! { #name::#variant }),
+    };
+
+    (
+        quote! {
+            (&mut #self_pat, &mut #other_pat) => {
+                #(#copies)*
+                return;
+            }
+        },
+        quote! {
+            #self_pat => {
+                #(#clears)*
+            }
+        },
+    )
+}